@@ -0,0 +1,239 @@
+use super::super::image_source::{ImageSource, TestPattern, CAMERA_HEIGHT, CAMERA_WIDTH};
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::super::utils::get_bit_at;
+use super::Cartridge;
+use std::fmt;
+
+const RAM_SIZE: usize = 0x20000;
+// Captured pictures land in RAM bank 0 as 16x14 tiles
+const IMAGE_ADDRESS: usize = 0x100;
+const EDGE_RATIOS: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+/// Pocket Camera cartridge, an MBC with 128KB of RAM and the M64282FP sensor.
+pub struct PocketCamera {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  rom_bank: u8,
+  ram_bank: u8,
+  is_ram_enabled: bool,
+  // Sensor registers, mapped at 0xa000 when RAM bank 0x10 is selected
+  registers: [u8; 0x36],
+  // T-cycles left until the running capture completes
+  capture_cycles: u32,
+  source: Box<dyn ImageSource>,
+}
+
+impl PocketCamera {
+  pub fn new(data: Vec<u8>) -> Self {
+    Self {
+      rom: data,
+      ram: vec![0; RAM_SIZE],
+      rom_bank: 1,
+      ram_bank: 0,
+      is_ram_enabled: false,
+      registers: [0; 0x36],
+      capture_cycles: 0,
+      source: Box::new(TestPattern),
+    }
+  }
+
+  fn read_rom(&self, address: u16, bank: u8) -> u8 {
+    let banks = (self.rom.len() / 0x4000).max(1);
+    let offset = (address & 0x3fff) as usize;
+    self
+      .rom
+      .get((bank as usize % banks) * 0x4000 + offset)
+      .unwrap_or(&0xff)
+      .to_owned()
+  }
+
+  fn is_sensor_mapped(&self) -> bool {
+    get_bit_at(self.ram_bank, 4)
+  }
+
+  fn ram_address(&self, address: u16) -> usize {
+    (self.ram_bank & 0x0f) as usize * 0x2000 + (address - 0xa000) as usize
+  }
+
+  fn exposure(&self) -> u32 {
+    (self.registers[2] as u32) << 8 | self.registers[3] as u32
+  }
+
+  fn write_register(&mut self, register: usize, data: u8) {
+    if register == 0 {
+      let starting = get_bit_at(data, 0) && self.capture_cycles == 0;
+      self.registers[0] = data & 0b0000_0111;
+      if starting {
+        // The sensor reads out 128x128 pixels at a quarter of the CPU clock
+        let extra = if get_bit_at(self.registers[1], 7) { 0 } else { 512 };
+        self.capture_cycles = 4 * (32446 + extra + 16 * self.exposure());
+      }
+    } else if register < self.registers.len() {
+      self.registers[register] = data;
+    }
+  }
+
+  // Exposure scales the sensor output, 0x1000 leaves it untouched
+  fn sensor_value(&self, image: &[u8], x: i32, y: i32) -> i32 {
+    let x = x.clamp(0, CAMERA_WIDTH as i32 - 1) as usize;
+    let y = y.clamp(0, CAMERA_HEIGHT as i32 - 1) as usize;
+    let value = image[y * CAMERA_WIDTH + x] as u32 * self.exposure() / 0x1000;
+    value.min(255) as i32
+  }
+
+  fn processed_value(&self, image: &[u8], x: i32, y: i32) -> u8 {
+    let center = self.sensor_value(image, x, y);
+    let ratio = EDGE_RATIOS[(self.registers[4] >> 4) as usize & 0b111];
+    // VH selects which neighbours take part in the edge enhancement
+    let value = match (self.registers[1] >> 5) & 0b11 {
+      0b00 => center,
+      mode => {
+        let mut neighbours = Vec::new();
+        if mode & 0b01 != 0 {
+          neighbours.push(self.sensor_value(image, x, y - 1));
+          neighbours.push(self.sensor_value(image, x, y + 1));
+        }
+        if mode & 0b10 != 0 {
+          neighbours.push(self.sensor_value(image, x - 1, y));
+          neighbours.push(self.sensor_value(image, x + 1, y));
+        }
+        let edge = center * neighbours.len() as i32 - neighbours.iter().sum::<i32>();
+        center + edge * ratio / 8
+      }
+    };
+    let value = value.clamp(0, 255) as u8;
+    if get_bit_at(self.registers[4], 3) {
+      255 - value
+    } else {
+      value
+    }
+  }
+
+  // Each pixel is compared against the three thresholds of its 4x4 matrix cell
+  fn capture(&mut self) {
+    let image = self.source.capture();
+    if image.len() < CAMERA_WIDTH * CAMERA_HEIGHT {
+      return;
+    }
+    for y in 0..CAMERA_HEIGHT {
+      for x in 0..CAMERA_WIDTH {
+        let value = self.processed_value(&image, x as i32, y as i32);
+        let cell = 6 + ((y & 3) * 4 + (x & 3)) * 3;
+        let thresholds = &self.registers[cell..cell + 3];
+        let color = thresholds.iter().filter(|threshold| value < **threshold).count() as u8;
+        let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+        let address = IMAGE_ADDRESS + tile * 16 + (y % 8) * 2;
+        let bit = 7 - (x % 8);
+        for plane in 0..2 {
+          let byte = &mut self.ram[address + plane];
+          *byte = (*byte & !(1 << bit)) | ((color >> plane) & 0b1) << bit;
+        }
+      }
+    }
+  }
+}
+
+impl Cartridge for PocketCamera {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x3fff => self.read_rom(address, 0),
+      0x4000..=0x7fff => self.read_rom(address, self.rom_bank),
+      // Only the capture status can be read back
+      0xa000..=0xbfff if self.is_sensor_mapped() => match address & 0x7f {
+        0x00 => self.registers[0],
+        _ => 0x00,
+      },
+      // RAM can't be read while a capture is writing it
+      0xa000..=0xbfff if self.capture_cycles > 0 => 0x00,
+      0xa000..=0xbfff => self.ram[self.ram_address(address)],
+      _ => unreachable!(),
+    }
+  }
+
+  fn write(&mut self, address: u16, data: u8) {
+    match address {
+      0x0000..=0x1fff => self.is_ram_enabled = data & 0xf == 0b1010,
+      0x2000..=0x3fff => self.rom_bank = data & 0b0011_1111,
+      0x4000..=0x5fff => self.ram_bank = data & 0b0001_1111,
+      0x6000..=0x7fff => {}
+      0xa000..=0xbfff if self.is_sensor_mapped() => {
+        self.write_register((address & 0x7f) as usize, data)
+      }
+      0xa000..=0xbfff if self.is_ram_enabled && self.capture_cycles == 0 => {
+        let ram_address = self.ram_address(address);
+        self.ram[ram_address] = data;
+      }
+      0xa000..=0xbfff => {}
+      _ => unreachable!(),
+    }
+  }
+
+  // Sensor registers stay writable with RAM disabled, so gating happens here
+  fn ram_enabled(&self) -> bool {
+    true
+  }
+
+  fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+    self.source = source;
+  }
+
+  fn tick(&mut self, cycles: u32) {
+    if self.capture_cycles == 0 {
+      return;
+    }
+    self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+    if self.capture_cycles == 0 {
+      self.capture();
+      self.registers[0] &= !0b1;
+    }
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    Some(self.ram.clone())
+  }
+
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let len = self.ram.len().min(data.len());
+    self.ram[..len].copy_from_slice(&data[..len]);
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.rom_bank);
+    state.write_u8(self.ram_bank);
+    state.write_bool(self.is_ram_enabled);
+    state.write_bytes(&self.registers);
+    state.write_u32(self.capture_cycles);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.rom_bank = state.read_u8()?;
+    self.ram_bank = state.read_u8()?;
+    self.is_ram_enabled = state.read_bool()?;
+    state.read_into(&mut self.registers)?;
+    self.capture_cycles = state.read_u32()?;
+    state.read_into(&mut self.ram)
+  }
+
+  fn debug(&self) {
+    println!("{:?}", self);
+  }
+}
+
+impl fmt::Debug for PocketCamera {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "CARTRIDGE ------------------------\n\
+      type: POCKET CAMERA\n\
+      ROM Bank: {}\n\
+      RAM Bank: {}\n\
+      RAM Enabled: {}\n\
+      Exposure: {:04X}\n",
+      self.rom_bank,
+      self.ram_bank,
+      self.is_ram_enabled,
+      self.exposure()
+    )
+  }
+}
@@ -0,0 +1,385 @@
+use super::{mapper_name, LoadError, NINTENDO_LOGO};
+use std::fmt;
+
+// Old licensee code telling that the new two letter code at 0x144 is used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum CgbSupport {
+  None,
+  // Runs on both DMG and CGB with color enhancements
+  Compatible,
+  Only,
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Destination {
+  Japan,
+  Overseas,
+}
+
+/// Every field of the cartridge header at 0x100-0x14f.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RomHeader {
+  pub title: String,
+  pub manufacturer_code: Option<String>,
+  pub old_licensee: u8,
+  pub new_licensee: Option<String>,
+  pub cgb: CgbSupport,
+  pub sgb: bool,
+  pub cartridge_type: u8,
+  pub rom_size_code: u8,
+  pub ram_size_code: u8,
+  pub destination: Destination,
+  pub version: u8,
+  pub header_checksum: u8,
+  pub computed_header_checksum: u8,
+  pub global_checksum: u16,
+  pub computed_global_checksum: u16,
+  pub logo_valid: bool,
+}
+
+fn ascii(bytes: &[u8]) -> String {
+  bytes
+    .iter()
+    .take_while(|byte| **byte != 0)
+    .map(|byte| *byte as char)
+    .collect::<String>()
+    .trim_end()
+    .to_string()
+}
+
+impl RomHeader {
+  // Nothing is validated besides the length, the checksums and logo are reported as found
+  pub fn parse(data: &[u8]) -> Result<Self, LoadError> {
+    if data.len() < 0x150 {
+      return Err(LoadError::Truncated {
+        expected: 0x150,
+        found: data.len(),
+      });
+    }
+    let cgb = match data[0x143] {
+      0xc0 => CgbSupport::Only,
+      flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+      _ => CgbSupport::None,
+    };
+    // Later CGB games shortened the title to 11 characters to fit a manufacturer code
+    let code = &data[0x13f..0x143];
+    let has_manufacturer_code = cgb != CgbSupport::None
+      && code
+        .iter()
+        .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+    let (title, manufacturer_code) = match (cgb, has_manufacturer_code) {
+      (_, true) => (ascii(&data[0x134..0x13f]), Some(ascii(code))),
+      (CgbSupport::None, _) => (ascii(&data[0x134..0x144]), None),
+      _ => (ascii(&data[0x134..0x143]), None),
+    };
+    let old_licensee = data[0x14b];
+    let new_licensee = if old_licensee == USE_NEW_LICENSEE {
+      Some(ascii(&data[0x144..0x146]))
+    } else {
+      None
+    };
+    let computed_global_checksum = data
+      .iter()
+      .enumerate()
+      .filter(|(address, _)| *address != 0x14e && *address != 0x14f)
+      .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16));
+    Ok(Self {
+      title,
+      manufacturer_code,
+      old_licensee,
+      new_licensee,
+      cgb,
+      // The SGB functions are only enabled with the new licensee code
+      sgb: data[0x146] == 0x03 && old_licensee == USE_NEW_LICENSEE,
+      cartridge_type: data[0x147],
+      rom_size_code: data[0x148],
+      ram_size_code: data[0x149],
+      destination: match data[0x14a] {
+        0x00 => Destination::Japan,
+        _ => Destination::Overseas,
+      },
+      version: data[0x14c],
+      header_checksum: data[0x14d],
+      computed_header_checksum: data[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1)),
+      global_checksum: (data[0x14e] as u16) << 8 | data[0x14f] as u16,
+      computed_global_checksum,
+      logo_valid: data[0x104..0x134] == NINTENDO_LOGO[..],
+    })
+  }
+
+  pub fn mapper(&self) -> &'static str {
+    mapper_name(self.cartridge_type)
+  }
+
+  pub fn rom_size(&self) -> Option<usize> {
+    match self.rom_size_code {
+      code @ 0x00..=0x08 => Some(0x8000 << code),
+      _ => None,
+    }
+  }
+
+  pub fn ram_size(&self) -> Option<usize> {
+    super::ram_size(self.ram_size_code).ok()
+  }
+
+  pub fn licensee(&self) -> &'static str {
+    match &self.new_licensee {
+      Some(code) => new_licensee_name(code),
+      None => old_licensee_name(self.old_licensee),
+    }
+  }
+
+  pub fn header_checksum_valid(&self) -> bool {
+    self.header_checksum == self.computed_header_checksum
+  }
+
+  pub fn global_checksum_valid(&self) -> bool {
+    self.global_checksum == self.computed_global_checksum
+  }
+}
+
+fn validity(valid: bool) -> &'static str {
+  if valid {
+    "valid"
+  } else {
+    "invalid"
+  }
+}
+
+fn size(size: Option<usize>) -> String {
+  match size {
+    Some(size) => format!("{}KB", size / 1024),
+    None => "Unknown".to_string(),
+  }
+}
+
+impl fmt::Display for RomHeader {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let licensee_code = match &self.new_licensee {
+      Some(code) => code.clone(),
+      None => format!("{:02X}h", self.old_licensee),
+    };
+    writeln!(f, "Title: {}", self.title)?;
+    if let Some(code) = &self.manufacturer_code {
+      writeln!(f, "Manufacturer: {}", code)?;
+    }
+    writeln!(f, "Licensee: {} ({})", self.licensee(), licensee_code)?;
+    writeln!(f, "CGB: {:?}", self.cgb)?;
+    writeln!(f, "SGB: {}", self.sgb)?;
+    writeln!(f, "Type: {} ({:02X}h)", self.mapper(), self.cartridge_type)?;
+    writeln!(f, "ROM size: {} ({:02X}h)", size(self.rom_size()), self.rom_size_code)?;
+    writeln!(f, "RAM size: {} ({:02X}h)", size(self.ram_size()), self.ram_size_code)?;
+    writeln!(f, "Destination: {:?}", self.destination)?;
+    writeln!(f, "Version: {}", self.version)?;
+    writeln!(
+      f,
+      "Header checksum: {:02X} ({})",
+      self.header_checksum,
+      validity(self.header_checksum_valid())
+    )?;
+    writeln!(
+      f,
+      "Global checksum: {:04X} ({})",
+      self.global_checksum,
+      validity(self.global_checksum_valid())
+    )?;
+    write!(f, "Nintendo logo: {}", validity(self.logo_valid))
+  }
+}
+
+pub fn new_licensee_name(code: &str) -> &'static str {
+  match code {
+    "00" => "None",
+    "01" => "Nintendo R&D1",
+    "08" => "Capcom",
+    "13" => "Electronic Arts",
+    "18" => "Hudson Soft",
+    "19" => "b-ai",
+    "20" => "KSS",
+    "22" => "POW",
+    "24" => "PCM Complete",
+    "25" => "San-X",
+    "28" => "Kemco Japan",
+    "29" => "Seta",
+    "30" => "Viacom",
+    "31" => "Nintendo",
+    "32" => "Bandai",
+    "33" => "Ocean/Acclaim",
+    "34" => "Konami",
+    "35" => "Hector",
+    "37" => "Taito",
+    "38" => "Hudson",
+    "39" => "Banpresto",
+    "41" => "Ubi Soft",
+    "42" => "Atlus",
+    "44" => "Malibu",
+    "46" => "Angel",
+    "47" => "Bullet-Proof",
+    "49" => "Irem",
+    "50" => "Absolute",
+    "51" => "Acclaim",
+    "52" => "Activision",
+    "53" => "American Sammy",
+    "54" => "Konami",
+    "55" => "Hi Tech Entertainment",
+    "56" => "LJN",
+    "57" => "Matchbox",
+    "58" => "Mattel",
+    "59" => "Milton Bradley",
+    "60" => "Titus",
+    "61" => "Virgin",
+    "64" => "LucasArts",
+    "67" => "Ocean",
+    "69" => "Electronic Arts",
+    "70" => "Infogrames",
+    "71" => "Interplay",
+    "72" => "Broderbund",
+    "73" => "Sculptured Software",
+    "75" => "The Sales Curve",
+    "78" => "THQ",
+    "79" => "Accolade",
+    "80" => "Misawa",
+    "83" => "LOZC",
+    "86" => "Tokuma Shoten",
+    "87" => "Tsukuda Original",
+    "91" => "Chunsoft",
+    "92" => "Video System",
+    "93" => "Ocean/Acclaim",
+    "95" => "Varie",
+    "96" => "Yonezawa/S'Pal",
+    "97" => "Kaneko",
+    "99" => "Pack-In-Video",
+    "A4" => "Konami",
+    _ => "Unknown",
+  }
+}
+
+pub fn old_licensee_name(code: u8) -> &'static str {
+  match code {
+    0x00 => "None",
+    0x01 | 0x31 => "Nintendo",
+    0x08 | 0x38 => "Capcom",
+    0x09 => "Hot-B",
+    0x0a | 0xe0 => "Jaleco",
+    0x0b => "Coconuts Japan",
+    0x0c | 0x6e => "Elite Systems",
+    0x13 | 0x69 => "Electronic Arts",
+    0x18 => "Hudson Soft",
+    0x19 => "ITC Entertainment",
+    0x1a => "Yanoman",
+    0x1d => "Japan Clary",
+    0x1f | 0x4a | 0x61 => "Virgin",
+    0x24 => "PCM Complete",
+    0x25 => "San-X",
+    0x28 => "Kotobuki Systems",
+    0x29 => "Seta",
+    0x30 | 0x70 => "Infogrames",
+    0x32 | 0xa2 | 0xb2 => "Bandai",
+    0x34 | 0xa4 => "Konami",
+    0x35 => "HectorSoft",
+    0x39 | 0x9d | 0xd9 => "Banpresto",
+    0x3c => "Entertainment Interactive",
+    0x3e => "Gremlin",
+    0x41 => "Ubi Soft",
+    0x42 | 0xeb => "Atlus",
+    0x44 | 0x4d => "Malibu",
+    0x46 | 0xcf => "Angel",
+    0x47 => "Spectrum HoloByte",
+    0x49 => "Irem",
+    0x4f => "U.S. Gold",
+    0x50 => "Absolute",
+    0x51 | 0xb0 => "Acclaim",
+    0x52 => "Activision",
+    0x53 => "American Sammy",
+    0x54 => "GameTek",
+    0x55 => "Park Place",
+    0x56 | 0xdb | 0xff => "LJN",
+    0x57 => "Matchbox",
+    0x59 => "Milton Bradley",
+    0x5a => "Mindscape",
+    0x5b => "Romstar",
+    0x5c | 0xd6 => "Naxat Soft",
+    0x5d => "Tradewest",
+    0x60 => "Titus",
+    0x67 => "Ocean",
+    0x6f => "Electro Brain",
+    0x71 => "Interplay",
+    0x72 | 0xaa => "Broderbund",
+    0x73 => "Sculptured Software",
+    0x75 => "The Sales Curve",
+    0x78 => "THQ",
+    0x79 => "Accolade",
+    0x7a => "Triffix Entertainment",
+    0x7c => "MicroProse",
+    0x7f | 0xc2 => "Kemco",
+    0x80 => "Misawa",
+    0x83 => "LOZC",
+    0x86 | 0xc4 => "Tokuma Shoten",
+    0x8b => "Bullet-Proof",
+    0x8c => "Vic Tokai",
+    0x8e => "Ape",
+    0x8f => "I'Max",
+    0x91 => "Chunsoft",
+    0x92 => "Video System",
+    0x93 => "Tsuburaya",
+    0x95 | 0xe3 => "Varie",
+    0x96 => "Yonezawa/S'Pal",
+    0x97 => "Kaneko",
+    0x99 => "Arc",
+    0x9a => "Nihon Bussan",
+    0x9b => "Tecmo",
+    0x9c => "Imagineer",
+    0x9f => "Nova",
+    0xa1 => "Hori Electric",
+    0xa6 => "Kawada",
+    0xa7 => "Takara",
+    0xa9 => "Technos Japan",
+    0xac => "Toei Animation",
+    0xad => "Toho",
+    0xaf => "Namco",
+    0xb1 => "ASCII/Nexsoft",
+    0xb4 => "Square Enix",
+    0xb6 => "HAL Laboratory",
+    0xb7 => "SNK",
+    0xb9 | 0xce => "Pony Canyon",
+    0xba => "Culture Brain",
+    0xbb => "Sunsoft",
+    0xbd => "Sony Imagesoft",
+    0xbf => "Sammy",
+    0xc0 | 0xd0 => "Taito",
+    0xc3 => "Squaresoft",
+    0xc5 => "Data East",
+    0xc6 => "Tonkin House",
+    0xc8 => "Koei",
+    0xc9 => "UFL",
+    0xca => "Ultra",
+    0xcb => "Vap",
+    0xcc => "Use",
+    0xcd => "Meldac",
+    0xd1 => "Sofel",
+    0xd2 => "Quest",
+    0xd3 => "Sigma Enterprises",
+    0xd4 => "ASK Kodansha",
+    0xd7 => "Copya System",
+    0xda => "Tomy",
+    0xdd => "NCS",
+    0xde => "Human",
+    0xdf => "Altron",
+    0xe1 => "Towa Chiki",
+    0xe2 => "Yutaka",
+    0xe5 => "Epoch",
+    0xe7 => "Athena",
+    0xe8 => "Asmik",
+    0xe9 => "Natsume",
+    0xea => "King Records",
+    0xec => "Epic/Sony Records",
+    0xee => "IGS",
+    0xf0 => "A Wave",
+    0xf3 => "Extreme Entertainment",
+    _ => "Unknown",
+  }
+}
@@ -0,0 +1,141 @@
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::{ram_size, Cartridge, LoadError};
+use std::fmt;
+
+pub struct HuC1 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  rom_bank: u8,
+  ram_bank: u8,
+  // 0xa000-0xbfff switches from RAM to the infrared port
+  is_ir_mode: bool,
+  ir_led: bool,
+  ir_light: bool,
+}
+
+impl HuC1 {
+  pub fn new(data: Vec<u8>) -> Result<Self, LoadError> {
+    let ram_size = ram_size(data[0x149])?;
+    Ok(Self {
+      rom: data,
+      ram: vec![0; ram_size],
+      rom_bank: 1,
+      ram_bank: 0,
+      is_ir_mode: false,
+      ir_led: false,
+      ir_light: false,
+    })
+  }
+
+  fn read_rom(&self, address: u16, bank: u8) -> u8 {
+    let banks = (self.rom.len() / 0x4000).max(1);
+    let offset = (address & 0x3fff) as usize;
+    self
+      .rom
+      .get((bank as usize % banks) * 0x4000 + offset)
+      .unwrap_or(&0xff)
+      .to_owned()
+  }
+
+  fn ram_address(&self, address: u16) -> usize {
+    self.ram_bank as usize * 0x2000 + (address - 0xa000) as usize
+  }
+}
+
+impl Cartridge for HuC1 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x3fff => self.read_rom(address, 0),
+      0x4000..=0x7fff => self.read_rom(address, self.rom_bank),
+      0xa000..=0xbfff if self.is_ir_mode => 0xc0 | self.ir_light as u8,
+      0xa000..=0xbfff => self
+        .ram
+        .get(self.ram_address(address))
+        .unwrap_or(&0xff)
+        .to_owned(),
+      _ => unreachable!(),
+    }
+  }
+
+  fn write(&mut self, address: u16, data: u8) {
+    match address {
+      0x0000..=0x1fff => self.is_ir_mode = data & 0xf == 0xe,
+      0x2000..=0x3fff => {
+        let bank = data & 0b0011_1111;
+        self.rom_bank = if bank == 0 { 1 } else { bank };
+      }
+      0x4000..=0x5fff => self.ram_bank = data & 0b11,
+      0x6000..=0x7fff => {}
+      0xa000..=0xbfff if self.is_ir_mode => self.ir_led = data & 0b1 == 1,
+      0xa000..=0xbfff => {
+        let ram_address = self.ram_address(address);
+        if let Some(byte) = self.ram.get_mut(ram_address) {
+          *byte = data;
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  // There is no RAM enable, the IR mode is the only other use of the area
+  fn ram_enabled(&self) -> bool {
+    true
+  }
+
+  fn ir_led(&self) -> bool {
+    self.ir_led
+  }
+
+  fn set_ir_light(&mut self, light: bool) {
+    self.ir_light = light;
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    Some(self.ram.clone())
+  }
+
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let len = self.ram.len().min(data.len());
+    self.ram[..len].copy_from_slice(&data[..len]);
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.rom_bank);
+    state.write_u8(self.ram_bank);
+    state.write_bool(self.is_ir_mode);
+    state.write_bool(self.ir_led);
+    state.write_bool(self.ir_light);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.rom_bank = state.read_u8()?;
+    self.ram_bank = state.read_u8()?;
+    self.is_ir_mode = state.read_bool()?;
+    self.ir_led = state.read_bool()?;
+    self.ir_light = state.read_bool()?;
+    state.read_into(&mut self.ram)
+  }
+
+  fn debug(&self) {
+    println!("{:?}", self);
+  }
+}
+
+impl fmt::Debug for HuC1 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "CARTRIDGE ------------------------\n\
+      type: HuC1\n\
+      ROM Bank: {}\n\
+      RAM Bank: {}\n\
+      RAM Size: {:X}\n\
+      IR Mode: {}\n",
+      self.rom_bank,
+      self.ram_bank,
+      self.ram.len(),
+      self.is_ir_mode
+    )
+  }
+}
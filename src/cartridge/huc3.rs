@@ -0,0 +1,269 @@
+use super::super::clock::{Clock, SystemClock};
+use super::super::constants::CPU_CLOCK_HZ;
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::{ram_size, Cartridge, LoadError};
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+// Timestamp, minutes, days, alarm minutes, alarm days and alarm enable, as written by SameBoy
+pub const HUC3_FOOTER_SIZE: usize = 17;
+
+const MINUTES_PER_DAY: u16 = 1440;
+const CYCLES_PER_MINUTE: u32 = CPU_CLOCK_HZ * 60;
+
+pub struct HuC3 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  rom_bank: u8,
+  ram_bank: u8,
+  // Selects what 0xa000-0xbfff maps to, RAM, RTC commands or infrared
+  mode: u8,
+  // Nibble wide scratch memory of the RTC chip, time is copied in and out at 0x00-0x05
+  rtc_memory: [u8; 0x100],
+  rtc_index: u8,
+  rtc_response: u8,
+  minutes: u16,
+  days: u16,
+  sub_minute: u32,
+  ir_led: bool,
+  ir_light: bool,
+  clock: Box<dyn Clock>,
+}
+
+impl HuC3 {
+  pub fn new(data: Vec<u8>) -> Result<Self, LoadError> {
+    let ram_size = ram_size(data[0x149])?;
+    Ok(Self {
+      rom: data,
+      ram: vec![0; ram_size],
+      rom_bank: 1,
+      ram_bank: 0,
+      mode: 0,
+      rtc_memory: [0; 0x100],
+      rtc_index: 0,
+      rtc_response: 0,
+      minutes: 0,
+      days: 0,
+      sub_minute: 0,
+      ir_led: false,
+      ir_light: false,
+      clock: Box::new(SystemClock),
+    })
+  }
+
+  fn read_rom(&self, address: u16, bank: u8) -> u8 {
+    let banks = (self.rom.len() / 0x4000).max(1);
+    let offset = (address & 0x3fff) as usize;
+    self
+      .rom
+      .get((bank as usize % banks) * 0x4000 + offset)
+      .unwrap_or(&0xff)
+      .to_owned()
+  }
+
+  fn ram_address(&self, address: u16) -> usize {
+    self.ram_bank as usize * 0x2000 + (address - 0xa000) as usize
+  }
+
+  fn advance_minutes(&mut self, minutes: u64) {
+    let total = self.minutes as u64 + minutes;
+    self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+    self.days = ((self.days as u64 + total / MINUTES_PER_DAY as u64) & 0xfff) as u16;
+  }
+
+  // Time registers are stored least significant nibble first
+  fn latch_time(&mut self) {
+    for nibble in 0..3 {
+      self.rtc_memory[nibble] = (self.minutes >> (nibble * 4)) as u8 & 0xf;
+      self.rtc_memory[nibble + 3] = (self.days >> (nibble * 4)) as u8 & 0xf;
+    }
+  }
+
+  fn set_time(&mut self) {
+    let memory = &self.rtc_memory;
+    let nibbles = |start: usize| {
+      (0..3).fold(0u16, |value, nibble| {
+        value | (memory[start + nibble] as u16) << (nibble * 4)
+      })
+    };
+    let (minutes, days) = (nibbles(0), nibbles(3));
+    self.minutes = minutes % MINUTES_PER_DAY;
+    self.days = days;
+    self.sub_minute = 0;
+  }
+
+  // Commands are a nibble each, with the argument or value in the low nibble
+  fn rtc_command(&mut self, data: u8) {
+    let argument = data & 0xf;
+    match data >> 4 {
+      0x1 => {
+        self.rtc_response = 0x10 | self.rtc_memory[self.rtc_index as usize];
+        self.rtc_index = self.rtc_index.wrapping_add(1);
+      }
+      0x3 => {
+        self.rtc_memory[self.rtc_index as usize] = argument;
+        self.rtc_index = self.rtc_index.wrapping_add(1);
+      }
+      0x4 => self.rtc_index = (self.rtc_index & 0xf0) | argument,
+      0x5 => self.rtc_index = (self.rtc_index & 0x0f) | argument << 4,
+      0x6 => match argument {
+        0x0 => self.latch_time(),
+        0x1 => self.set_time(),
+        // Status, the chip is always ready
+        0x2 => self.rtc_response = 0x61,
+        // Speaker tones are not emulated
+        _ => {}
+      },
+      _ => {}
+    }
+  }
+}
+
+impl Cartridge for HuC3 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x3fff => self.read_rom(address, 0),
+      0x4000..=0x7fff => self.read_rom(address, self.rom_bank),
+      0xa000..=0xbfff => match self.mode {
+        0x0 | 0xa => self
+          .ram
+          .get(self.ram_address(address))
+          .unwrap_or(&0xff)
+          .to_owned(),
+        0xc => self.rtc_response,
+        // Semaphore, commands complete instantly
+        0xd => 0xff,
+        0xe => 0xc0 | self.ir_light as u8,
+        _ => 0xff,
+      },
+      _ => unreachable!(),
+    }
+  }
+
+  fn write(&mut self, address: u16, data: u8) {
+    match address {
+      0x0000..=0x1fff => self.mode = data & 0xf,
+      0x2000..=0x3fff => self.rom_bank = data & 0b0111_1111,
+      0x4000..=0x5fff => self.ram_bank = data & 0b11,
+      0x6000..=0x7fff => {}
+      0xa000..=0xbfff => match self.mode {
+        0xa => {
+          let ram_address = self.ram_address(address);
+          if let Some(byte) = self.ram.get_mut(ram_address) {
+            *byte = data;
+          }
+        }
+        0xb => self.rtc_command(data),
+        0xe => self.ir_led = data & 0b1 == 1,
+        _ => {}
+      },
+      _ => unreachable!(),
+    }
+  }
+
+  // Access is decided by the mode register instead
+  fn ram_enabled(&self) -> bool {
+    true
+  }
+
+  fn set_clock(&mut self, clock: Box<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  fn tick(&mut self, cycles: u32) {
+    self.sub_minute += cycles;
+    if self.sub_minute >= CYCLES_PER_MINUTE {
+      self.sub_minute -= CYCLES_PER_MINUTE;
+      self.advance_minutes(1);
+    }
+  }
+
+  fn ir_led(&self) -> bool {
+    self.ir_led
+  }
+
+  fn set_ir_light(&mut self, light: bool) {
+    self.ir_light = light;
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    let mut data = self.ram.clone();
+    let mut footer = [0; HUC3_FOOTER_SIZE];
+    LittleEndian::write_i64(&mut footer[0..8], self.clock.now());
+    LittleEndian::write_u16(&mut footer[8..10], self.minutes);
+    LittleEndian::write_u16(&mut footer[10..12], self.days);
+    data.extend_from_slice(&footer);
+    Some(data)
+  }
+
+  // The clock catches up on the real time that passed since the game was saved
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let ram_len = self.ram.len().min(data.len());
+    self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+    let footer = match data.get(self.ram.len()..self.ram.len() + HUC3_FOOTER_SIZE) {
+      Some(footer) => footer,
+      None => return,
+    };
+    self.minutes = LittleEndian::read_u16(&footer[8..10]) % MINUTES_PER_DAY;
+    self.days = LittleEndian::read_u16(&footer[10..12]) & 0xfff;
+    self.sub_minute = 0;
+    let saved_at = LittleEndian::read_i64(&footer[0..8]);
+    if let Some(elapsed) = self.clock.now().checked_sub(saved_at) {
+      if elapsed > 0 {
+        self.advance_minutes(elapsed as u64 / 60);
+      }
+    }
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.rom_bank);
+    state.write_u8(self.ram_bank);
+    state.write_u8(self.mode);
+    state.write_bytes(&self.rtc_memory);
+    state.write_u8(self.rtc_index);
+    state.write_u8(self.rtc_response);
+    state.write_u16(self.minutes);
+    state.write_u16(self.days);
+    state.write_u32(self.sub_minute);
+    state.write_bool(self.ir_led);
+    state.write_bool(self.ir_light);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.rom_bank = state.read_u8()?;
+    self.ram_bank = state.read_u8()?;
+    self.mode = state.read_u8()?;
+    state.read_into(&mut self.rtc_memory)?;
+    self.rtc_index = state.read_u8()?;
+    self.rtc_response = state.read_u8()?;
+    self.minutes = state.read_u16()?;
+    self.days = state.read_u16()?;
+    self.sub_minute = state.read_u32()?;
+    self.ir_led = state.read_bool()?;
+    self.ir_light = state.read_bool()?;
+    state.read_into(&mut self.ram)
+  }
+
+  fn debug(&self) {
+    println!("{:?}", self);
+  }
+}
+
+impl fmt::Debug for HuC3 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "CARTRIDGE ------------------------\n\
+      type: HuC3\n\
+      ROM Bank: {}\n\
+      RAM Bank: {}\n\
+      RAM Size: {:X}\n\
+      Mode: {:X}\n",
+      self.rom_bank,
+      self.ram_bank,
+      self.ram.len(),
+      self.mode
+    )
+  }
+}
@@ -1,5 +1,6 @@
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
 use super::Bmode;
-use super::Cartridge;
+use super::{ram_size, Cartridge, LoadError, NINTENDO_LOGO};
 use std::fmt;
 
 pub struct MBC1 {
@@ -10,19 +11,21 @@ pub struct MBC1 {
   ram_size: u16,
   banking_mode: Bmode,
   is_ram_enabled: bool,
+  has_battery: bool,
+  // MBC1M wires the upper bank bits to A18-A19, leaving 16 banks per game
+  is_multicart: bool,
 }
 
 impl MBC1 {
-  pub fn new(data: Vec<u8>) -> Self {
+  pub fn new(data: Vec<u8>) -> Result<Self, LoadError> {
     let rom_size = 32 << data[0x148];
     let ram_size = match data[0x149] {
-      0 => 0,
-      1 => 0x800,
-      2 => 0x2000,
-      3 => 0x8000,
-      _ => panic!("Unsupported ram size"),
+      code @ 0..=3 => ram_size(code)?,
+      code => return Err(LoadError::InvalidRamSize(code)),
     };
-    Self {
+    let has_battery = data[0x147] == 0x03;
+    let is_multicart = is_multicart(&data);
+    Ok(Self {
       rom: data,
       ram: vec![0; ram_size],
       memory_bank: 1,
@@ -30,7 +33,9 @@ impl MBC1 {
       ram_size: ram_size as u16,
       banking_mode: Bmode::ROM,
       is_ram_enabled: false,
-    }
+      has_battery,
+      is_multicart,
+    })
   }
 
   fn read_rom(&self, address: u16, bank: u8) -> u8 {
@@ -77,9 +82,19 @@ impl MBC1 {
   }
 
   fn get_bank2_as_hi(&self) -> u8 {
+    if self.is_multicart {
+      return (self.memory_bank >> 1) & 0b0011_0000;
+    }
     self.memory_bank & 0b0110_0000
   }
 
+  fn get_rom_bank(&self) -> u8 {
+    if self.is_multicart {
+      return self.get_bank2_as_hi() | (self.memory_bank & 0b0000_1111);
+    }
+    self.memory_bank
+  }
+
   fn set_bank1(&mut self, data: u8) {
     let lower_bits = data & 0b0001_1111;
     let upper_bits = self.memory_bank & 0b0110_0000;
@@ -99,6 +114,15 @@ impl MBC1 {
   }
 }
 
+// 8Mbit compilations repeat the header at the start of every game, bank 0x10 is the first one
+fn is_multicart(data: &[u8]) -> bool {
+  let has_logo = |bank: usize| {
+    let start = bank * 0x4000 + 0x104;
+    data.get(start..start + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..])
+  };
+  data.len() == 0x100000 && has_logo(0x10)
+}
+
 impl Cartridge for MBC1 {
   fn read(&self, address: u16) -> u8 {
     match address {
@@ -106,7 +130,7 @@ impl Cartridge for MBC1 {
       0x0000..=0x3fff if self.banking_mode == Bmode::RAM => {
         self.read_rom(address, self.get_bank2_as_hi() % self.rom_size)
       }
-      0x4000..=0x7fff => self.read_rom(address, self.memory_bank % self.rom_size),
+      0x4000..=0x7fff => self.read_rom(address, self.get_rom_bank() % self.rom_size),
       0xa000..=0xbfff if self.banking_mode == Bmode::ROM => self.read_ram(address, 0),
       0xa000..=0xbfff if self.banking_mode == Bmode::RAM => {
         self.read_ram(address, self.get_bank2_as_low())
@@ -140,6 +164,37 @@ impl Cartridge for MBC1 {
     self.is_ram_enabled
   }
 
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    if self.has_battery {
+      Some(self.ram.clone())
+    } else {
+      None
+    }
+  }
+
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let len = self.ram.len().min(data.len());
+    self.ram[..len].copy_from_slice(&data[..len]);
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.memory_bank);
+    state.write_bool(self.banking_mode == Bmode::RAM);
+    state.write_bool(self.is_ram_enabled);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.memory_bank = state.read_u8()?;
+    self.banking_mode = if state.read_bool()? {
+      Bmode::RAM
+    } else {
+      Bmode::ROM
+    };
+    self.is_ram_enabled = state.read_bool()?;
+    state.read_into(&mut self.ram)
+  }
+
   fn debug(&self) {
     println!("{:?}", self);
   }
@@ -150,13 +205,18 @@ impl fmt::Debug for MBC1 {
     write!(
       f,
       "CARTRIDGE ------------------------\n\
-      type: MBC1\n\
+      type: {}\n\
       Bank: {}\n\
       ROM Size: {}\n\
       RAM Size: {:X}\n\
       Banking Mode: {:?}\n\
       RAM Enabled: {}\n",
-      self.memory_bank, self.rom_size, self.ram_size, self.banking_mode, self.is_ram_enabled,
+      if self.is_multicart { "MBC1M" } else { "MBC1" },
+      self.memory_bank,
+      self.rom_size,
+      self.ram_size,
+      self.banking_mode,
+      self.is_ram_enabled,
     )
   }
 }
@@ -1,3 +1,4 @@
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
 use super::Cartridge;
 use std::fmt;
 
@@ -8,6 +9,7 @@ pub struct MBC2 {
   rom_size: u8,
   ram_size: u16,
   is_ram_enabled: bool,
+  has_battery: bool,
 }
 
 impl MBC2 {
@@ -16,6 +18,7 @@ impl MBC2 {
     if rom_size > 16 {
       rom_size = 16;
     }
+    let has_battery = data[0x147] == 0x06;
     Self {
       rom: data,
       ram: vec![0; 0x200],
@@ -23,6 +26,7 @@ impl MBC2 {
       rom_size,
       ram_size: 0x200,
       is_ram_enabled: false,
+      has_battery,
     }
   }
 
@@ -91,6 +95,31 @@ impl Cartridge for MBC2 {
     self.is_ram_enabled
   }
 
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    if self.has_battery {
+      Some(self.ram.clone())
+    } else {
+      None
+    }
+  }
+
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let len = self.ram.len().min(data.len());
+    self.ram[..len].copy_from_slice(&data[..len]);
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.memory_bank);
+    state.write_bool(self.is_ram_enabled);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.memory_bank = state.read_u8()?;
+    self.is_ram_enabled = state.read_bool()?;
+    state.read_into(&mut self.ram)
+  }
+
   fn debug(&self) {
     println!("{:?}", self);
   }
@@ -1,5 +1,7 @@
-use super::Cartridge;
-use chrono::{Datelike, Timelike, Utc};
+use super::super::clock::{Clock, SystemClock};
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::rtc::{Rtc, RTC_FOOTER_SIZE_32};
+use super::{ram_size, Cartridge, LoadError};
 use std::fmt;
 
 pub struct MBC3 {
@@ -11,24 +13,21 @@ pub struct MBC3 {
   ram_size: u16,
   is_ram_enabled: bool,
   prev_bit: u8,
-  sec_reg: u8,
-  min_reg: u8,
-  hrs_reg: u8,
-  dayl_reg: u8,
-  dayh_reg: u8,
+  has_battery: bool,
+  has_timer: bool,
+  rtc: Rtc,
+  clock: Box<dyn Clock>,
 }
 
 impl MBC3 {
-  pub fn new(data: Vec<u8>) -> Self {
+  pub fn new(data: Vec<u8>) -> Result<Self, LoadError> {
     let rom_size = 32 << data[0x148];
     let ram_size = match data[0x149] {
-      0 => 0,
-      1 => 0x800,
-      2 => 0x2000,
-      3 => 0x8000,
-      _ => panic!("Unsupported ram size"),
+      code @ 0..=3 => ram_size(code)?,
+      code => return Err(LoadError::InvalidRamSize(code)),
     };
-    Self {
+    let cartridge_type = data[0x147];
+    Ok(Self {
       rom: data,
       ram: vec![0; ram_size],
       rom_bank: 1,
@@ -37,12 +36,11 @@ impl MBC3 {
       ram_size: ram_size as u16,
       is_ram_enabled: false,
       prev_bit: 0,
-      sec_reg: 0,
-      min_reg: 0,
-      hrs_reg: 0,
-      dayl_reg: 0,
-      dayh_reg: 0,
-    }
+      has_battery: matches!(cartridge_type, 0x0f | 0x10 | 0x13),
+      has_timer: matches!(cartridge_type, 0x0f | 0x10),
+      rtc: Rtc::default(),
+      clock: Box::new(SystemClock),
+    })
   }
 
   fn read_rom(&self, address: u16, bank: u8) -> u8 {
@@ -72,12 +70,8 @@ impl MBC3 {
           .unwrap_or(&0xff)
           .to_owned()
       }
-      0x8 => self.sec_reg,
-      0x9 => self.min_reg,
-      0xa => self.hrs_reg,
-      0xb => self.dayl_reg,
-      0xc => self.dayh_reg,
-      _ => unreachable!(),
+      0x8..=0xc if self.has_timer => self.rtc.read(bank),
+      _ => 0xff,
     }
   }
 
@@ -95,19 +89,11 @@ impl MBC3 {
         }
         self.ram[ram_address as usize] = data;
       }
-      0x8 => self.sec_reg = data,
-      0x9 => self.min_reg = data,
-      0xa => self.hrs_reg = data,
-      0xb => self.dayl_reg = data,
-      0xc => self.dayh_reg = data,
-      _ => unreachable!(),
+      0x8..=0xc if self.has_timer => self.rtc.write(bank, data),
+      _ => {}
     }
   }
 
-  fn get_bank2_as_low(&self) -> u8 {
-    self.rom_bank >> 5
-  }
-
   fn set_bank1(&mut self, data: u8) {
     let bank = data & 0b0111_1111;
     if bank == 0 {
@@ -144,18 +130,13 @@ impl Cartridge for MBC3 {
         0x0 => self.prev_bit = 0,
         0x1 => {
           if self.prev_bit == 0 {
-            let now = Utc::now();
-            self.sec_reg = now.second() as u8;
-            self.min_reg = now.minute() as u8;
-            self.hrs_reg = now.hour() as u8;
-            self.dayl_reg = now.day() as u8;
-            self.dayh_reg = (now.day() >> 8 & 0b1) as u8;
+            self.rtc.latch();
           }
           self.prev_bit = 1;
         }
         _ => unreachable!(),
       },
-      0xa000..=0xbfff => self.write_ram(address, self.get_bank2_as_low(), data),
+      0xa000..=0xbfff => self.write_ram(address, self.ram_bank, data),
       _ => unreachable!(),
     };
   }
@@ -164,6 +145,61 @@ impl Cartridge for MBC3 {
     self.is_ram_enabled
   }
 
+  fn set_clock(&mut self, clock: Box<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  fn tick(&mut self, cycles: u32) {
+    if self.has_timer {
+      self.rtc.tick(cycles);
+    }
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    if !self.has_battery {
+      return None;
+    }
+    let mut data = self.ram.clone();
+    if self.has_timer {
+      data.extend(self.rtc.footer(self.clock.now()));
+    }
+    Some(data)
+  }
+
+  // The clock catches up on the real time that passed since the game was saved
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let ram_len = self.ram.len().min(data.len());
+    self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+    if !self.has_timer || data.len() < self.ram.len() + RTC_FOOTER_SIZE_32 {
+      return;
+    }
+    if let Some(saved_at) = self.rtc.load_footer(&data[self.ram.len()..]) {
+      if let Some(elapsed) = self.clock.now().checked_sub(saved_at) {
+        if elapsed > 0 {
+          self.rtc.advance(elapsed as u64);
+        }
+      }
+    }
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.rom_bank);
+    state.write_u8(self.ram_bank);
+    state.write_bool(self.is_ram_enabled);
+    state.write_u8(self.prev_bit);
+    self.rtc.save_state(state);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.rom_bank = state.read_u8()?;
+    self.ram_bank = state.read_u8()?;
+    self.is_ram_enabled = state.read_bool()?;
+    self.prev_bit = state.read_u8()?;
+    self.rtc.load_state(state)?;
+    state.read_into(&mut self.ram)
+  }
+
   fn debug(&self) {
     println!("{:?}", self);
   }
@@ -0,0 +1,338 @@
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::super::utils::get_bit_at;
+use super::Cartridge;
+use std::fmt;
+
+// Latched reading with the cartridge held flat, one g moves it by about 0x70
+const ACCELEROMETER_CENTER: u16 = 0x81d0;
+const ACCELEROMETER_G: f32 = 112.0;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum EepromState {
+  Idle,
+  Command,
+  Read,
+  Write { address: Option<u8> },
+}
+
+/// 93LC56 serial EEPROM, 128 words of 16 bits driven bit by bit through one register.
+struct Eeprom {
+  words: [u16; 128],
+  state: EepromState,
+  write_enabled: bool,
+  cs: bool,
+  clk: bool,
+  di: bool,
+  do_bit: bool,
+  shift: u16,
+  bits: u8,
+  read_address: u8,
+}
+
+impl Eeprom {
+  fn new() -> Self {
+    Self {
+      words: [0xffff; 128],
+      state: EepromState::Idle,
+      write_enabled: false,
+      cs: false,
+      clk: false,
+      di: false,
+      do_bit: true,
+      shift: 0,
+      bits: 0,
+      read_address: 0,
+    }
+  }
+
+  fn read(&self) -> u8 {
+    (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.do_bit as u8
+  }
+
+  fn write(&mut self, data: u8) {
+    let cs = get_bit_at(data, 7);
+    let clk = get_bit_at(data, 6);
+    self.di = get_bit_at(data, 1);
+    if !cs {
+      self.state = EepromState::Idle;
+    } else if clk && !self.clk {
+      self.clock_in();
+    }
+    self.cs = cs;
+    self.clk = clk;
+  }
+
+  // Commands are a start bit, a 2-bit opcode and an 8-bit address, MSB first
+  fn clock_in(&mut self) {
+    let bit = self.di as u16;
+    match self.state {
+      EepromState::Idle => {
+        if self.di {
+          self.state = EepromState::Command;
+          self.shift = 0;
+          self.bits = 0;
+        }
+      }
+      EepromState::Command => {
+        self.shift = self.shift << 1 | bit;
+        self.bits += 1;
+        if self.bits == 10 {
+          self.execute((self.shift >> 8) as u8, self.shift as u8);
+        }
+      }
+      // A dummy zero is followed by the words, reads continue to the next address
+      EepromState::Read => {
+        if self.bits == 0 {
+          self.shift = self.words[self.read_address as usize];
+          self.read_address = (self.read_address + 1) & 0x7f;
+          self.bits = 16;
+        }
+        self.do_bit = get_bit_at((self.shift >> 8) as u8, 7);
+        self.shift <<= 1;
+        self.bits -= 1;
+      }
+      EepromState::Write { address } => {
+        self.shift = self.shift << 1 | bit;
+        self.bits += 1;
+        if self.bits == 16 {
+          match address {
+            Some(address) => self.words[address as usize] = self.shift,
+            None => self.words = [self.shift; 128],
+          }
+          self.do_bit = true;
+          self.state = EepromState::Idle;
+        }
+      }
+    }
+  }
+
+  fn execute(&mut self, opcode: u8, address: u8) {
+    let word = address & 0x7f;
+    self.state = EepromState::Idle;
+    self.shift = 0;
+    self.bits = 0;
+    match opcode {
+      0b10 => {
+        self.read_address = word;
+        self.do_bit = false;
+        self.state = EepromState::Read;
+      }
+      0b01 if self.write_enabled => self.state = EepromState::Write { address: Some(word) },
+      0b11 if self.write_enabled => {
+        self.words[word as usize] = 0xffff;
+        self.do_bit = true;
+      }
+      0b00 => match address >> 6 {
+        0b00 => self.write_enabled = false,
+        0b01 if self.write_enabled => self.state = EepromState::Write { address: None },
+        0b10 if self.write_enabled => {
+          self.words = [0xffff; 128];
+          self.do_bit = true;
+        }
+        0b11 => self.write_enabled = true,
+        _ => {}
+      },
+      _ => {}
+    }
+  }
+
+  // Stored little endian, the layout other emulators use for .sav files
+  fn to_bytes(&self) -> Vec<u8> {
+    self.words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+  }
+
+  fn load_bytes(&mut self, data: &[u8]) {
+    for (word, bytes) in self.words.iter_mut().zip(data.chunks_exact(2)) {
+      *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+    }
+  }
+}
+
+pub struct MBC7 {
+  rom: Vec<u8>,
+  rom_bank: u8,
+  // Both enables have to be set before the registers respond
+  ram_enable_1: bool,
+  ram_enable_2: bool,
+  tilt_x: f32,
+  tilt_y: f32,
+  latched_x: u16,
+  latched_y: u16,
+  eeprom: Eeprom,
+}
+
+impl MBC7 {
+  pub fn new(data: Vec<u8>) -> Self {
+    Self {
+      rom: data,
+      rom_bank: 1,
+      ram_enable_1: false,
+      ram_enable_2: false,
+      tilt_x: 0.0,
+      tilt_y: 0.0,
+      latched_x: 0x8000,
+      latched_y: 0x8000,
+      eeprom: Eeprom::new(),
+    }
+  }
+
+  fn read_rom(&self, address: u16, bank: u8) -> u8 {
+    let banks = (self.rom.len() / 0x4000).max(1);
+    let offset = (address & 0x3fff) as usize;
+    self
+      .rom
+      .get((bank as usize % banks) * 0x4000 + offset)
+      .unwrap_or(&0xff)
+      .to_owned()
+  }
+
+  fn accelerometer(tilt: f32) -> u16 {
+    (ACCELEROMETER_CENTER as f32 + tilt * ACCELEROMETER_G) as u16
+  }
+
+  fn read_register(&self, address: u16) -> u8 {
+    match (address >> 4) & 0xf {
+      0x2 => self.latched_x as u8,
+      0x3 => (self.latched_x >> 8) as u8,
+      0x4 => self.latched_y as u8,
+      0x5 => (self.latched_y >> 8) as u8,
+      0x6 => 0x00,
+      0x8 => self.eeprom.read(),
+      _ => 0xff,
+    }
+  }
+
+  // Latching only works after an erase, games always write 0x55 then 0xaa
+  fn write_register(&mut self, address: u16, data: u8) {
+    match (address >> 4) & 0xf {
+      0x0 if data == 0x55 => {
+        self.latched_x = 0x8000;
+        self.latched_y = 0x8000;
+      }
+      0x1 if data == 0xaa && self.latched_x == 0x8000 && self.latched_y == 0x8000 => {
+        self.latched_x = Self::accelerometer(self.tilt_x);
+        self.latched_y = Self::accelerometer(self.tilt_y);
+      }
+      0x8 => self.eeprom.write(data),
+      _ => {}
+    }
+  }
+}
+
+impl Cartridge for MBC7 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x3fff => self.read_rom(address, 0),
+      0x4000..=0x7fff => self.read_rom(address, self.rom_bank),
+      0xa000..=0xafff if self.ram_enable_1 && self.ram_enable_2 => self.read_register(address),
+      0xa000..=0xbfff => 0xff,
+      _ => unreachable!(),
+    }
+  }
+
+  fn write(&mut self, address: u16, data: u8) {
+    match address {
+      0x0000..=0x1fff => self.ram_enable_1 = data == 0x0a,
+      0x2000..=0x3fff => self.rom_bank = data & 0b0111_1111,
+      0x4000..=0x5fff => self.ram_enable_2 = data == 0x40,
+      0x6000..=0x7fff => {}
+      0xa000..=0xafff if self.ram_enable_1 && self.ram_enable_2 => {
+        self.write_register(address, data)
+      }
+      0xa000..=0xbfff => {}
+      _ => unreachable!(),
+    }
+  }
+
+  fn ram_enabled(&self) -> bool {
+    true
+  }
+
+  fn set_tilt(&mut self, x: f32, y: f32) {
+    self.tilt_x = x;
+    self.tilt_y = y;
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    Some(self.eeprom.to_bytes())
+  }
+
+  fn load_battery_save(&mut self, data: &[u8]) {
+    self.eeprom.load_bytes(data);
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.rom_bank);
+    state.write_bool(self.ram_enable_1);
+    state.write_bool(self.ram_enable_2);
+    state.write_u16(self.latched_x);
+    state.write_u16(self.latched_y);
+    state.write_bytes(&self.eeprom.to_bytes());
+    let (state_id, address) = match self.eeprom.state {
+      EepromState::Idle => (0, 0),
+      EepromState::Command => (1, 0),
+      EepromState::Read => (2, 0),
+      EepromState::Write { address: Some(address) } => (3, address),
+      EepromState::Write { address: None } => (4, 0),
+    };
+    state.write_u8(state_id);
+    state.write_u8(address);
+    state.write_bool(self.eeprom.write_enabled);
+    state.write_u8(self.eeprom.read());
+    state.write_u16(self.eeprom.shift);
+    state.write_u8(self.eeprom.bits);
+    state.write_u8(self.eeprom.read_address);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.rom_bank = state.read_u8()?;
+    self.ram_enable_1 = state.read_bool()?;
+    self.ram_enable_2 = state.read_bool()?;
+    self.latched_x = state.read_u16()?;
+    self.latched_y = state.read_u16()?;
+    let mut words = [0; 256];
+    state.read_into(&mut words)?;
+    self.eeprom.load_bytes(&words);
+    let state_id = state.read_u8()?;
+    let address = state.read_u8()?;
+    self.eeprom.state = match state_id {
+      1 => EepromState::Command,
+      2 => EepromState::Read,
+      3 => EepromState::Write {
+        address: Some(address),
+      },
+      4 => EepromState::Write { address: None },
+      _ => EepromState::Idle,
+    };
+    self.eeprom.write_enabled = state.read_bool()?;
+    let pins = state.read_u8()?;
+    self.eeprom.cs = get_bit_at(pins, 7);
+    self.eeprom.clk = get_bit_at(pins, 6);
+    self.eeprom.di = get_bit_at(pins, 1);
+    self.eeprom.do_bit = get_bit_at(pins, 0);
+    self.eeprom.shift = state.read_u16()?;
+    self.eeprom.bits = state.read_u8()?;
+    self.eeprom.read_address = state.read_u8()?;
+    Ok(())
+  }
+
+  fn debug(&self) {
+    println!("{:?}", self);
+  }
+}
+
+impl fmt::Debug for MBC7 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "CARTRIDGE ------------------------\n\
+      type: MBC7\n\
+      ROM Bank: {}\n\
+      RAM Enabled: {}\n\
+      EEPROM State: {:?}\n",
+      self.rom_bank,
+      self.ram_enable_1 && self.ram_enable_2,
+      self.eeprom.state
+    )
+  }
+}
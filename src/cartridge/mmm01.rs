@@ -0,0 +1,230 @@
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::super::utils::get_bit_at;
+use super::header::RomHeader;
+use super::{ram_size, Cartridge, LoadError};
+use std::fmt;
+
+// The menu and its header usually live in the last 32KB, bank 0 holding the first game.
+// That area is plain game code on other carts, so the header must be a valid one.
+pub fn menu_header_offset(data: &[u8]) -> Option<usize> {
+  let menu = data.len().checked_sub(0x8000)?;
+  let header = RomHeader::parse(&data[menu..]).ok()?;
+  let is_mmm01 = matches!(header.cartridge_type, 0x0b..=0x0d);
+  if is_mmm01 && header.logo_valid && header.header_checksum_valid() {
+    Some(menu)
+  } else {
+    None
+  }
+}
+
+pub struct MMM01 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  // Until the lock bit is set the last 32KB are mapped and every register is writable
+  is_mapped: bool,
+  is_ram_enabled: bool,
+  has_battery: bool,
+  rom_bank_low: u8,
+  rom_bank_mid: u8,
+  rom_bank_high: u8,
+  // Set bits of ROM bank 1-4 are frozen once mapped, they select the game
+  rom_bank_mask: u8,
+  ram_bank_low: u8,
+  ram_bank_high: u8,
+  ram_bank_mask: u8,
+  ram_banking_mode: bool,
+  is_mode_locked: bool,
+}
+
+impl MMM01 {
+  pub fn new(data: Vec<u8>) -> Result<Self, LoadError> {
+    let header = menu_header_offset(&data).unwrap_or(0);
+    let ram_size = ram_size(data[header + 0x149])?;
+    let has_battery = data[header + 0x147] == 0x0d;
+    Ok(Self {
+      rom: data,
+      ram: vec![0; ram_size],
+      is_mapped: false,
+      is_ram_enabled: false,
+      has_battery,
+      rom_bank_low: 0,
+      rom_bank_mid: 0,
+      rom_bank_high: 0,
+      rom_bank_mask: 0,
+      ram_bank_low: 0,
+      ram_bank_high: 0,
+      ram_bank_mask: 0,
+      ram_banking_mode: false,
+      is_mode_locked: false,
+    })
+  }
+
+  fn read_rom(&self, address: u16, bank: usize) -> u8 {
+    let banks = (self.rom.len() / 0x4000).max(1);
+    let offset = (address & 0x3fff) as usize;
+    self
+      .rom
+      .get((bank % banks) * 0x4000 + offset)
+      .unwrap_or(&0xff)
+      .to_owned()
+  }
+
+  // Bank selected by the fixed bits, the start of the current game
+  fn game_bank(&self) -> usize {
+    let frozen_low = self.rom_bank_low & (self.rom_bank_mask << 1);
+    (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5 | frozen_low as usize
+  }
+
+  fn rom_bank(&self) -> usize {
+    let low = if self.rom_bank_low == 0 { 1 } else { self.rom_bank_low };
+    (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5 | low as usize
+  }
+
+  fn ram_address(&self, address: u16) -> usize {
+    let low = if self.ram_banking_mode { self.ram_bank_low } else { 0 };
+    let bank = (self.ram_bank_high << 2 | low) as usize;
+    bank * 0x2000 + (address - 0xa000) as usize
+  }
+
+  // Once mapped, masked bits keep their value
+  fn masked_write(old: u8, data: u8, frozen: u8) -> u8 {
+    (old & frozen) | (data & !frozen)
+  }
+}
+
+impl Cartridge for MMM01 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x7fff if !self.is_mapped => {
+        let banks = self.rom.len() / 0x4000;
+        let bank = banks - 2 + (address >= 0x4000) as usize;
+        self.read_rom(address, bank)
+      }
+      0x0000..=0x3fff => self.read_rom(address, self.game_bank()),
+      0x4000..=0x7fff => self.read_rom(address, self.rom_bank()),
+      0xa000..=0xbfff => {
+        let ram_address = self.ram_address(address);
+        self.ram.get(ram_address).unwrap_or(&0xff).to_owned()
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  fn write(&mut self, address: u16, data: u8) {
+    match address {
+      0x0000..=0x1fff => {
+        self.is_ram_enabled = data & 0xf == 0b1010;
+        if !self.is_mapped {
+          self.ram_bank_mask = (data >> 4) & 0b11;
+          self.is_mapped = get_bit_at(data, 6);
+        }
+      }
+      0x2000..=0x3fff => {
+        if self.is_mapped {
+          let frozen = self.rom_bank_mask << 1;
+          self.rom_bank_low = Self::masked_write(self.rom_bank_low, data & 0x1f, frozen);
+        } else {
+          self.rom_bank_low = data & 0x1f;
+          self.rom_bank_mid = (data >> 5) & 0b11;
+        }
+      }
+      0x4000..=0x5fff => {
+        if self.is_mapped {
+          let frozen = self.ram_bank_mask;
+          self.ram_bank_low = Self::masked_write(self.ram_bank_low, data & 0b11, frozen);
+        } else {
+          self.ram_bank_low = data & 0b11;
+          self.ram_bank_high = (data >> 2) & 0b11;
+          self.rom_bank_high = (data >> 4) & 0b11;
+          self.is_mode_locked = get_bit_at(data, 6);
+        }
+      }
+      0x6000..=0x7fff => {
+        if !self.is_mapped {
+          self.rom_bank_mask = (data >> 2) & 0xf;
+        }
+        if !self.is_mode_locked {
+          self.ram_banking_mode = get_bit_at(data, 0);
+        }
+      }
+      0xa000..=0xbfff => {
+        let ram_address = self.ram_address(address);
+        if let Some(byte) = self.ram.get_mut(ram_address) {
+          *byte = data;
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  fn ram_enabled(&self) -> bool {
+    self.is_ram_enabled
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    if self.has_battery {
+      Some(self.ram.clone())
+    } else {
+      None
+    }
+  }
+
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let len = self.ram.len().min(data.len());
+    self.ram[..len].copy_from_slice(&data[..len]);
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_bool(self.is_mapped);
+    state.write_bool(self.is_ram_enabled);
+    state.write_u8(self.rom_bank_low);
+    state.write_u8(self.rom_bank_mid);
+    state.write_u8(self.rom_bank_high);
+    state.write_u8(self.rom_bank_mask);
+    state.write_u8(self.ram_bank_low);
+    state.write_u8(self.ram_bank_high);
+    state.write_u8(self.ram_bank_mask);
+    state.write_bool(self.ram_banking_mode);
+    state.write_bool(self.is_mode_locked);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.is_mapped = state.read_bool()?;
+    self.is_ram_enabled = state.read_bool()?;
+    self.rom_bank_low = state.read_u8()?;
+    self.rom_bank_mid = state.read_u8()?;
+    self.rom_bank_high = state.read_u8()?;
+    self.rom_bank_mask = state.read_u8()?;
+    self.ram_bank_low = state.read_u8()?;
+    self.ram_bank_high = state.read_u8()?;
+    self.ram_bank_mask = state.read_u8()?;
+    self.ram_banking_mode = state.read_bool()?;
+    self.is_mode_locked = state.read_bool()?;
+    state.read_into(&mut self.ram)
+  }
+
+  fn debug(&self) {
+    println!("{:?}", self);
+  }
+}
+
+impl fmt::Debug for MMM01 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "CARTRIDGE ------------------------\n\
+      type: MMM01\n\
+      Mapped: {}\n\
+      Game Bank: {}\n\
+      ROM Bank: {}\n\
+      RAM Size: {:X}\n\
+      RAM Enabled: {}\n",
+      self.is_mapped,
+      self.game_bank(),
+      self.rom_bank(),
+      self.ram.len(),
+      self.is_ram_enabled
+    )
+  }
+}
@@ -1,7 +1,20 @@
+pub mod camera;
+pub mod header;
+pub mod huc1;
+pub mod huc3;
 pub mod mbc1;
 pub mod mbc2;
 pub mod mbc3;
+pub mod mbc7;
+pub mod mmm01;
 pub mod rom_only;
+pub mod rtc;
+pub mod tama5;
+use super::clock::Clock;
+use header::RomHeader;
+use super::image_source::ImageSource;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
+use std::fmt;
 use std::path::PathBuf;
 
 pub trait Cartridge {
@@ -10,9 +23,189 @@ pub trait Cartridge {
   fn ram_enabled(&self) -> bool {
     false
   }
+  // Only cartridges with a real-time clock use it
+  fn set_clock(&mut self, _clock: Box<dyn Clock>) {}
+  // Only the Pocket Camera has a sensor to feed
+  fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+  // Advances hardware that runs on its own crystal, in normal speed T-cycles
+  fn tick(&mut self, _cycles: u32) {}
+  // Battery backed contents in the .sav layout, None for cartridges without a battery
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    None
+  }
+  fn load_battery_save(&mut self, _data: &[u8]) {}
+  // Infrared port of HuC carts, polled by frontends or a link transport
+  fn ir_led(&self) -> bool {
+    false
+  }
+  fn set_ir_light(&mut self, _light: bool) {}
+  // Accelerometer of MBC7 carts, in g along each axis
+  fn set_tilt(&mut self, _x: f32, _y: f32) {}
+  fn save_state(&self, state: &mut StateWriter);
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError>;
   fn debug(&self);
 }
 
+#[derive(Debug)]
+pub enum LoadError {
+  Io(std::io::Error),
+  Truncated { expected: usize, found: usize },
+  UnsupportedMapper(u8),
+  InvalidRomSize(u8),
+  InvalidRamSize(u8),
+  HeaderChecksum { expected: u8, found: u8 },
+}
+
+impl fmt::Display for LoadError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LoadError::Io(e) => write!(f, "{}", e),
+      LoadError::Truncated { expected, found } => write!(
+        f,
+        "ROM is truncated, expected {} bytes but found {}",
+        expected, found
+      ),
+      LoadError::UnsupportedMapper(code) => write!(
+        f,
+        "MBC case not supported {:02X}h {}",
+        code,
+        mapper_name(*code)
+      ),
+      LoadError::InvalidRomSize(code) => write!(f, "invalid ROM size {:02X}h", code),
+      LoadError::InvalidRamSize(code) => write!(f, "invalid RAM size {:02X}h", code),
+      LoadError::HeaderChecksum { expected, found } => write!(
+        f,
+        "header checksum mismatch, expected {:02X} but found {:02X}",
+        expected, found
+      ),
+    }
+  }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+  fn from(e: std::io::Error) -> Self {
+    LoadError::Io(e)
+  }
+}
+
+// Checked by the boot ROM at 0x104, also marks each game on multicarts
+pub const NINTENDO_LOGO: [u8; 48] = [
+  0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+  0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+  0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+pub fn mapper_name(code: u8) -> &'static str {
+  match code {
+    0x00 => "ROM ONLY",
+    0x01 => "MBC1",
+    0x02 => "MBC1+RAM",
+    0x03 => "MBC1+RAM+BATTERY",
+    0x05 => "MBC2",
+    0x06 => "MBC2+BATTERY",
+    0x08 => "ROM+RAM",
+    0x09 => "ROM+RAM+BATTERY",
+    0x0b => "MMM01",
+    0x0c => "MMM01+RAM",
+    0x0d => "MMM01+RAM+BATTERY",
+    0x0f => "MBC3+TIMER+BATTERY",
+    0x10 => "MBC3+TIMER+RAM+BATTERY",
+    0x11 => "MBC3",
+    0x12 => "MBC3+RAM",
+    0x13 => "MBC3+RAM+BATTERY",
+    0x15 => "MBC4",
+    0x16 => "MBC4+RAM",
+    0x17 => "MBC4+RAM+BATTERY",
+    0x19 => "MBC5",
+    0x1a => "MBC5+RAM",
+    0x1b => "MBC5+RAM+BATTERY",
+    0x1c => "MBC5+RUMBLE",
+    0x1d => "MBC5+RUMBLE+RAM",
+    0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+    0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+    0xfc => "POCKET CAMERA",
+    0xfd => "BANDAI TAMA5",
+    0xfe => "HuC3",
+    0xff => "HuC1+RAM+BATTERY",
+    _ => "Unknown",
+  }
+}
+
+pub fn ram_size(code: u8) -> Result<usize, LoadError> {
+  match code {
+    0 => Ok(0),
+    1 => Ok(0x800),
+    2 => Ok(0x2000),
+    3 => Ok(0x8000),
+    4 => Ok(0x20000),
+    5 => Ok(0x10000),
+    _ => Err(LoadError::InvalidRamSize(code)),
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CartridgeInfo {
+  pub title: String,
+  pub cartridge_type: u8,
+  pub rom_size: usize,
+  pub ram_size: usize,
+  pub sgb: bool,
+}
+
+impl CartridgeInfo {
+  // Checks everything the boot ROM and the mappers rely on. The global checksum
+  // is not verified by the hardware and many ROMs get it wrong, so it's ignored.
+  pub fn parse(data: &[u8]) -> Result<Self, LoadError> {
+    Self::parse_at(data, 0)
+  }
+
+  /// Parses the header found at `offset`, such as the menu header of an MMM01 compilation.
+  pub fn parse_at(data: &[u8], offset: usize) -> Result<Self, LoadError> {
+    let header = RomHeader::parse(data.get(offset..).unwrap_or(&[]))?;
+    if !header.header_checksum_valid() {
+      return Err(LoadError::HeaderChecksum {
+        expected: header.computed_header_checksum,
+        found: header.header_checksum,
+      });
+    }
+    let rom_size = header
+      .rom_size()
+      .ok_or(LoadError::InvalidRomSize(header.rom_size_code))?;
+    if data.len() < rom_size {
+      return Err(LoadError::Truncated {
+        expected: rom_size,
+        found: data.len(),
+      });
+    }
+    Ok(Self {
+      ram_size: ram_size(header.ram_size_code)?,
+      title: header.title,
+      cartridge_type: header.cartridge_type,
+      rom_size,
+      sgb: header.sgb,
+    })
+  }
+
+  pub fn mapper(&self) -> &'static str {
+    mapper_name(self.cartridge_type)
+  }
+}
+
+impl fmt::Display for CartridgeInfo {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} ({}, ROM: {}KB, RAM: {}KB)",
+      self.title,
+      self.mapper(),
+      self.rom_size / 1024,
+      self.ram_size / 1024
+    )
+  }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Bmode {
   RAM,
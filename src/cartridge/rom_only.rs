@@ -1,3 +1,4 @@
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
 use super::Cartridge;
 use std::fmt;
 
@@ -25,6 +26,12 @@ impl Cartridge for RomOnly {
 
   fn write(&mut self, _address: u16, _data: u8) {}
 
+  fn save_state(&self, _state: &mut StateWriter) {}
+
+  fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SnapshotError> {
+    Ok(())
+  }
+
   fn debug(&self) {
     println!("{:?}", self);
   }
@@ -0,0 +1,236 @@
+use super::super::constants::CPU_CLOCK_HZ;
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::super::utils::get_bit_at;
+use byteorder::{ByteOrder, LittleEndian};
+
+// Size of the VBA/BGB save footer, older files store the timestamp in 4 bytes
+pub const RTC_FOOTER_SIZE: usize = 48;
+pub const RTC_FOOTER_SIZE_32: usize = 44;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// MBC3 real-time clock, counting emulated time from its own 32768Hz crystal.
+pub struct Rtc {
+  seconds: u8,
+  minutes: u8,
+  hours: u8,
+  days: u16,
+  halt: bool,
+  carry: bool,
+  // Registers as seen by the CPU, refreshed on latch
+  latched: [u8; 5],
+  // T-cycles since the last second at normal speed
+  sub_second: u32,
+}
+
+impl Rtc {
+  pub fn default() -> Self {
+    Self {
+      seconds: 0,
+      minutes: 0,
+      hours: 0,
+      days: 0,
+      halt: false,
+      carry: false,
+      latched: [0; 5],
+      sub_second: 0,
+    }
+  }
+
+  pub fn tick(&mut self, cycles: u32) {
+    if self.halt {
+      return;
+    }
+    self.sub_second += cycles;
+    while self.sub_second >= CPU_CLOCK_HZ {
+      self.sub_second -= CPU_CLOCK_HZ;
+      self.advance_second();
+    }
+  }
+
+  // Counters wrap at their register width, so out of range values never carry
+  fn advance_second(&mut self) {
+    self.seconds = (self.seconds + 1) & 0x3f;
+    if self.seconds != 60 {
+      return;
+    }
+    self.seconds = 0;
+    self.minutes = (self.minutes + 1) & 0x3f;
+    if self.minutes != 60 {
+      return;
+    }
+    self.minutes = 0;
+    self.hours = (self.hours + 1) & 0x1f;
+    if self.hours != 24 {
+      return;
+    }
+    self.hours = 0;
+    self.add_days(1);
+  }
+
+  fn add_days(&mut self, days: u64) {
+    let days = self.days as u64 + days;
+    if days > 0x1ff {
+      self.carry = true;
+    }
+    self.days = (days % 0x200) as u16;
+  }
+
+  /// Catches up on time that passed while the emulator wasn't running.
+  pub fn advance(&mut self, mut seconds: u64) {
+    if self.halt {
+      return;
+    }
+    // Out of range registers wrap back within hours, then whole days can be added at once
+    while seconds > 0 && !(self.seconds < 60 && self.minutes < 60 && self.hours < 24) {
+      self.advance_second();
+      seconds -= 1;
+    }
+    self.add_days(seconds / SECONDS_PER_DAY);
+    for _ in 0..seconds % SECONDS_PER_DAY {
+      self.advance_second();
+    }
+  }
+
+  fn registers(&self) -> [u8; 5] {
+    [
+      self.seconds,
+      self.minutes,
+      self.hours,
+      self.days as u8,
+      (self.days >> 8) as u8 | (self.halt as u8) << 6 | (self.carry as u8) << 7,
+    ]
+  }
+
+  pub fn latch(&mut self) {
+    self.latched = self.registers();
+  }
+
+  pub fn read(&self, register: u8) -> u8 {
+    match register {
+      0x8 => self.latched[0] & 0x3f,
+      0x9 => self.latched[1] & 0x3f,
+      0xa => self.latched[2] & 0x1f,
+      0xb => self.latched[3],
+      0xc => self.latched[4] & 0b1100_0001,
+      _ => 0xff,
+    }
+  }
+
+  pub fn write(&mut self, register: u8, data: u8) {
+    match register {
+      0x8 => {
+        self.seconds = data & 0x3f;
+        self.sub_second = 0;
+      }
+      0x9 => self.minutes = data & 0x3f,
+      0xa => self.hours = data & 0x1f,
+      0xb => self.days = (self.days & 0x100) | data as u16,
+      0xc => {
+        self.days = (self.days & 0xff) | ((data & 0b1) as u16) << 8;
+        self.halt = get_bit_at(data, 6);
+        self.carry = get_bit_at(data, 7);
+      }
+      _ => return,
+    }
+    // Written values show up without waiting for the next latch
+    self.latched[(register - 0x8) as usize] = self.registers()[(register - 0x8) as usize];
+  }
+
+  /// Live and latched registers as 32-bit words followed by the 64-bit save time.
+  pub fn footer(&self, timestamp: i64) -> Vec<u8> {
+    let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+    for register in self.registers().iter().chain(self.latched.iter()) {
+      footer.extend_from_slice(&(*register as u32).to_le_bytes());
+    }
+    footer.extend_from_slice(&timestamp.to_le_bytes());
+    footer
+  }
+
+  /// Restores the clock from a save footer and returns the save time.
+  pub fn load_footer(&mut self, footer: &[u8]) -> Option<i64> {
+    let timestamp = match footer.len() {
+      RTC_FOOTER_SIZE => LittleEndian::read_i64(&footer[40..48]),
+      RTC_FOOTER_SIZE_32 => LittleEndian::read_u32(&footer[40..44]) as i64,
+      _ => return None,
+    };
+    let word = |index: usize| footer[index * 4];
+    self.seconds = word(0) & 0x3f;
+    self.minutes = word(1) & 0x3f;
+    self.hours = word(2) & 0x1f;
+    self.days = word(3) as u16 | ((word(4) & 0b1) as u16) << 8;
+    self.halt = get_bit_at(word(4), 6);
+    self.carry = get_bit_at(word(4), 7);
+    for (index, register) in self.latched.iter_mut().enumerate() {
+      *register = word(index + 5);
+    }
+    self.sub_second = 0;
+    Some(timestamp)
+  }
+
+  pub fn save_state(&self, state: &mut StateWriter) {
+    state.write_bytes(&self.registers());
+    state.write_bytes(&self.latched);
+    state.write_u32(self.sub_second);
+  }
+
+  pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    let mut registers = [0; 5];
+    state.read_into(&mut registers)?;
+    self.seconds = registers[0];
+    self.minutes = registers[1];
+    self.hours = registers[2];
+    self.days = registers[3] as u16 | ((registers[4] & 0b1) as u16) << 8;
+    self.halt = get_bit_at(registers[4], 6);
+    self.carry = get_bit_at(registers[4], 7);
+    state.read_into(&mut self.latched)?;
+    self.sub_second = state.read_u32()?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_rtc_rollover() {
+  let mut rtc = Rtc::default();
+  rtc.write(0x8, 59);
+  rtc.write(0x9, 59);
+  rtc.write(0xa, 23);
+  rtc.write(0xb, 0xff);
+  rtc.write(0xc, 0x01);
+  rtc.tick(CPU_CLOCK_HZ);
+  rtc.latch();
+  assert_eq!(
+    (rtc.read(0x8), rtc.read(0x9), rtc.read(0xa), rtc.read(0xb), rtc.read(0xc)),
+    (0, 0, 0, 0, 0x80)
+  );
+
+  // Out of range values count up to the register width without carrying
+  rtc.write(0x8, 63);
+  rtc.tick(CPU_CLOCK_HZ);
+  rtc.latch();
+  assert_eq!((rtc.read(0x8), rtc.read(0x9)), (0, 0));
+
+  rtc.write(0xc, 0x40);
+  rtc.tick(CPU_CLOCK_HZ * 10);
+  rtc.advance(1000);
+  rtc.latch();
+  assert_eq!(rtc.read(0x8), 0);
+}
+
+#[test]
+fn test_rtc_footer() {
+  let mut rtc = Rtc::default();
+  rtc.write(0xa, 5);
+  rtc.advance(SECONDS_PER_DAY * 3 + 61);
+  let footer = rtc.footer(1_000);
+  assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+
+  let mut restored = Rtc::default();
+  assert_eq!(restored.load_footer(&footer), Some(1_000));
+  restored.latch();
+  assert_eq!(
+    (restored.read(0x8), restored.read(0x9), restored.read(0xa), restored.read(0xb)),
+    (1, 1, 5, 3)
+  );
+  assert_eq!(restored.load_footer(&footer[..RTC_FOOTER_SIZE_32]), Some(1_000));
+}
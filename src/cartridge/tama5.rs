@@ -0,0 +1,347 @@
+use super::super::clock::{Clock, SystemClock};
+use super::super::constants::CPU_CLOCK_HZ;
+use super::super::snapshot::{SnapshotError, StateReader, StateWriter};
+use super::Cartridge;
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+const RAM_SIZE: usize = 0x20;
+// Timestamp followed by seconds, minutes, hours, weekday, day, month and year
+pub const TAMA5_FOOTER_SIZE: usize = 15;
+const SECONDS_PER_DAY: u64 = 86_400;
+// Years wrap at 100 and every fourth one is a leap year, so the calendar repeats
+const DAYS_PER_CENTURY: u64 = 36_525;
+// Exclusive upper bound of each clock field, from seconds to year
+const FIELD_LIMITS: [u8; 7] = [60, 60, 24, 7, 32, 13, 100];
+
+// Registers selected through 0xa001, each holds a nibble
+const ROM_BANK_LOW: usize = 0x0;
+const ROM_BANK_HIGH: usize = 0x1;
+const WRITE_LOW: usize = 0x4;
+const WRITE_HIGH: usize = 0x5;
+const ADDRESS_HIGH: usize = 0x6;
+const ADDRESS_LOW: usize = 0x7;
+const READ_LOW: usize = 0xc;
+const READ_HIGH: usize = 0xd;
+
+/// Calendar clock of the TAMA6 chip, read and written as BCD nibbles.
+struct TamaClock {
+  seconds: u8,
+  minutes: u8,
+  hours: u8,
+  weekday: u8,
+  day: u8,
+  month: u8,
+  year: u8,
+  sub_second: u32,
+}
+
+impl TamaClock {
+  fn default() -> Self {
+    Self {
+      seconds: 0,
+      minutes: 0,
+      hours: 0,
+      weekday: 0,
+      day: 1,
+      month: 1,
+      year: 0,
+      sub_second: 0,
+    }
+  }
+
+  fn days_in_month(&self) -> u8 {
+    match self.month {
+      2 if self.year & 0b11 == 0 => 29,
+      2 => 28,
+      4 | 6 | 9 | 11 => 30,
+      _ => 31,
+    }
+  }
+
+  fn advance_day(&mut self) {
+    self.weekday = (self.weekday + 1) % 7;
+    self.day += 1;
+    if self.day <= self.days_in_month() {
+      return;
+    }
+    self.day = 1;
+    self.month += 1;
+    if self.month > 12 {
+      self.month = 1;
+      self.year = (self.year + 1) % 100;
+    }
+  }
+
+  fn advance_second(&mut self) {
+    self.seconds += 1;
+    if self.seconds < 60 {
+      return;
+    }
+    self.seconds = 0;
+    self.minutes += 1;
+    if self.minutes < 60 {
+      return;
+    }
+    self.minutes = 0;
+    self.hours += 1;
+    if self.hours < 24 {
+      return;
+    }
+    self.hours = 0;
+    self.advance_day();
+  }
+
+  fn tick(&mut self, cycles: u32) {
+    self.sub_second += cycles;
+    while self.sub_second >= CPU_CLOCK_HZ {
+      self.sub_second -= CPU_CLOCK_HZ;
+      self.advance_second();
+    }
+  }
+
+  fn advance(&mut self, seconds: u64) {
+    let days = seconds / SECONDS_PER_DAY;
+    // Whole centuries only move the weekday
+    let skipped = days - days % DAYS_PER_CENTURY;
+    self.weekday = ((self.weekday as u64 + skipped) % 7) as u8;
+    for _ in 0..days % DAYS_PER_CENTURY {
+      self.advance_day();
+    }
+    for _ in 0..seconds % SECONDS_PER_DAY {
+      self.advance_second();
+    }
+  }
+
+  fn fields_mut(&mut self) -> [&mut u8; 7] {
+    [
+      &mut self.seconds,
+      &mut self.minutes,
+      &mut self.hours,
+      &mut self.weekday,
+      &mut self.day,
+      &mut self.month,
+      &mut self.year,
+    ]
+  }
+
+  // Even registers are the units digit of each field, odd ones the tens
+  fn read(&self, register: u8) -> u8 {
+    let field = match self.to_bytes().get(register as usize / 2) {
+      Some(field) => *field,
+      None => return 0,
+    };
+    if register & 0b1 == 0 {
+      field % 10
+    } else {
+      field / 10
+    }
+  }
+
+  fn write(&mut self, register: u8, data: u8) {
+    let mut fields = self.fields_mut();
+    if let Some(field) = fields.get_mut(register as usize / 2) {
+      let (tens, units) = (**field / 10, **field % 10);
+      let value = if register & 0b1 == 0 {
+        tens * 10 + data.min(9)
+      } else {
+        data.min(9) * 10 + units
+      };
+      **field = value.min(FIELD_LIMITS[register as usize / 2] - 1);
+    }
+    self.sub_second = 0;
+  }
+
+  fn to_bytes(&self) -> [u8; 7] {
+    [
+      self.seconds,
+      self.minutes,
+      self.hours,
+      self.weekday,
+      self.day,
+      self.month,
+      self.year,
+    ]
+  }
+
+  // Rejects fields a running clock could never hold, they would overflow when counting
+  fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+    if bytes.len() != FIELD_LIMITS.len()
+      || bytes.iter().zip(&FIELD_LIMITS).any(|(byte, limit)| byte >= limit)
+    {
+      return Err(SnapshotError::InvalidValue);
+    }
+    for (field, byte) in self.fields_mut().iter_mut().zip(bytes) {
+      **field = *byte;
+    }
+    Ok(())
+  }
+}
+
+pub struct TAMA5 {
+  rom: Vec<u8>,
+  ram: Vec<u8>,
+  registers: [u8; 0x10],
+  selected: usize,
+  rtc: TamaClock,
+  clock: Box<dyn Clock>,
+}
+
+impl TAMA5 {
+  pub fn new(data: Vec<u8>) -> Self {
+    Self {
+      rom: data,
+      ram: vec![0; RAM_SIZE],
+      registers: [0; 0x10],
+      selected: 0,
+      rtc: TamaClock::default(),
+      clock: Box::new(SystemClock),
+    }
+  }
+
+  fn rom_bank(&self) -> usize {
+    (self.registers[ROM_BANK_HIGH] as usize & 0b1) << 4 | self.registers[ROM_BANK_LOW] as usize
+  }
+
+  fn read_rom(&self, address: u16, bank: usize) -> u8 {
+    let banks = (self.rom.len() / 0x4000).max(1);
+    let offset = (address & 0x3fff) as usize;
+    self
+      .rom
+      .get((bank % banks) * 0x4000 + offset)
+      .unwrap_or(&0xff)
+      .to_owned()
+  }
+
+  // Writing the low address nibble runs the command held in the upper address register
+  fn execute(&mut self) {
+    let address = (self.registers[ADDRESS_HIGH] as usize & 0b1) << 4
+      | self.registers[ADDRESS_LOW] as usize;
+    let data = self.registers[WRITE_HIGH] << 4 | self.registers[WRITE_LOW];
+    let result = match self.registers[ADDRESS_HIGH] >> 1 {
+      0x0 => {
+        self.ram[address] = data;
+        return;
+      }
+      0x1 => self.ram[address],
+      0x2 => {
+        self.rtc.write(address as u8 & 0xf, data & 0xf);
+        return;
+      }
+      0x3 => self.rtc.read(address as u8 & 0xf),
+      _ => return,
+    };
+    self.registers[READ_LOW] = result & 0xf;
+    self.registers[READ_HIGH] = result >> 4;
+  }
+}
+
+impl Cartridge for TAMA5 {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      0x0000..=0x3fff => self.read_rom(address, 0),
+      0x4000..=0x7fff => self.read_rom(address, self.rom_bank()),
+      // Every other register reads as ready
+      0xa000 => match self.selected {
+        READ_LOW | READ_HIGH => 0xf0 | self.registers[self.selected],
+        _ => 0xf1,
+      },
+      0xa001..=0xbfff => 0xff,
+      _ => unreachable!(),
+    }
+  }
+
+  fn write(&mut self, address: u16, data: u8) {
+    match address {
+      0x0000..=0x7fff => {}
+      0xa000 => {
+        self.registers[self.selected] = data & 0xf;
+        if self.selected == ADDRESS_LOW {
+          self.execute();
+        }
+      }
+      0xa001 => self.selected = (data & 0xf) as usize,
+      0xa002..=0xbfff => {}
+      _ => unreachable!(),
+    }
+  }
+
+  // The register window is always reachable
+  fn ram_enabled(&self) -> bool {
+    true
+  }
+
+  fn set_clock(&mut self, clock: Box<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  fn tick(&mut self, cycles: u32) {
+    self.rtc.tick(cycles);
+  }
+
+  fn battery_save(&self) -> Option<Vec<u8>> {
+    let mut data = self.ram.clone();
+    let mut timestamp = [0; 8];
+    LittleEndian::write_i64(&mut timestamp, self.clock.now());
+    data.extend_from_slice(&timestamp);
+    data.extend_from_slice(&self.rtc.to_bytes());
+    Some(data)
+  }
+
+  // The clock catches up on the real time that passed since the game was saved
+  fn load_battery_save(&mut self, data: &[u8]) {
+    let ram_len = self.ram.len().min(data.len());
+    self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+    let footer = match data.get(RAM_SIZE..RAM_SIZE + TAMA5_FOOTER_SIZE) {
+      Some(footer) => footer,
+      None => return,
+    };
+    // A corrupted footer leaves the clock where it was
+    if self.rtc.load_bytes(&footer[8..]).is_err() {
+      return;
+    }
+    self.rtc.sub_second = 0;
+    let saved_at = LittleEndian::read_i64(&footer[..8]);
+    if let Some(elapsed) = self.clock.now().checked_sub(saved_at) {
+      if elapsed > 0 {
+        self.rtc.advance(elapsed as u64);
+      }
+    }
+  }
+
+  fn save_state(&self, state: &mut StateWriter) {
+    state.write_bytes(&self.registers);
+    state.write_u8(self.selected as u8);
+    state.write_bytes(&self.rtc.to_bytes());
+    state.write_u32(self.rtc.sub_second);
+    state.write_bytes(&self.ram);
+  }
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    state.read_into(&mut self.registers)?;
+    self.selected = state.read_u8()? as usize & 0xf;
+    let mut fields = [0; 7];
+    state.read_into(&mut fields)?;
+    self.rtc.load_bytes(&fields)?;
+    self.rtc.sub_second = state.read_u32()?;
+    state.read_into(&mut self.ram)
+  }
+
+  fn debug(&self) {
+    println!("{:?}", self);
+  }
+}
+
+impl fmt::Debug for TAMA5 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "CARTRIDGE ------------------------\n\
+      type: TAMA5\n\
+      ROM Bank: {}\n\
+      Register: {:X}\n",
+      self.rom_bank(),
+      self.selected
+    )
+  }
+}
@@ -0,0 +1,24 @@
+use chrono::Utc;
+
+/// Source of wall clock time for cartridges with a real-time clock,
+/// in seconds since the unix epoch.
+pub trait Clock {
+    fn now(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+}
+
+// Always reports the same time, used by movies and tests for deterministic runs
+pub struct FixedClock(pub i64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
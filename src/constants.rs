@@ -13,3 +13,11 @@ pub const STEPS: bool = false;
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
+// What the screen shows while the LCD is off
+pub const LCD_OFF_COLOR: u32 = 0xff_ff_ff;
+// Super Game Boy output, the screen framed by the border
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+
+pub const CPU_CLOCK_HZ: u32 = 4_194_304;
+pub const DOTS_PER_FRAME: u32 = 70_224;
@@ -1,6 +1,9 @@
 use super::alu::*;
+use super::constants::DIVIDER_COUNTER_ADDRESS;
 use super::dispatcher::Action;
-use super::emulator::Emulator;
+use super::emulator::{Emulator, Event};
+use super::interrupts;
+use super::memory::Model;
 use super::registers::Flags;
 use super::utils::*;
 
@@ -1149,7 +1152,9 @@ fn execute_opcode(ctx: &mut Emulator, opcode: u8, is_callback: bool) {
             ctx.registers.a = rrc_n(ctx.registers.a, &mut ctx.registers);
             ctx.registers.set_flag(Flags::Z, false);
         }
-        0x10 => {}
+        0x10 => {
+            stop(ctx);
+        }
         0x11 => {
             let data = ctx.get_word();
             ctx.registers.set_de(data);
@@ -1540,7 +1545,7 @@ fn execute_opcode(ctx: &mut Emulator, opcode: u8, is_callback: bool) {
             ctx.mem_write(hl, l);
         }
         0x76 => {
-            ctx.timers.is_halted = true;
+            halt(ctx);
         }
         0x77 => {
             let a = ctx.registers.a;
@@ -1997,14 +2002,41 @@ fn execute_opcode(ctx: &mut Emulator, opcode: u8, is_callback: bool) {
         0xff => {
             rst_n(0x0038, ctx);
         }
+        // Illegal opcodes hang the CPU until reset
         0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
-            unreachable!("Unexisting code {:X}", opcode)
+            ctx.timers.is_locked = true;
+            let address = ctx.memory.get_pc().wrapping_sub(1);
+            ctx.emit(Event::CpuLocked { opcode, address });
         }
     };
 }
 
+// With IME=0 and an interrupt already pending HALT is skipped and the
+// next opcode byte is read twice (halt bug)
+fn halt(ctx: &mut Emulator) {
+    if !ctx.timers.ime && interrupts::pending(ctx) != 0 {
+        ctx.timers.halt_bug = true;
+    } else {
+        ctx.timers.is_halted = true;
+    }
+}
+
+// STOP is two bytes long, the second byte is skipped
+fn stop(ctx: &mut Emulator) {
+    ctx.memory.inc_pc(1);
+    ctx.memory.timer.write(DIVIDER_COUNTER_ADDRESS, 0);
+    if ctx.memory.model == Model::Cgb && ctx.memory.speed_switch_armed {
+        ctx.memory.speed_switch_armed = false;
+        ctx.memory.double_speed = !ctx.memory.double_speed;
+        return;
+    }
+    // Only lines going low from here on wake the CPU
+    ctx.memory.joypad.take_line_fall();
+    ctx.timers.is_stopped = true;
+}
+
 pub fn update(emulator: &mut Emulator) {
-    if !emulator.timers.is_halted {
+    if !emulator.timers.is_halted && !emulator.timers.is_stopped && !emulator.timers.is_locked {
         let opcode = emulator.fetch_opcode();
         emulator.take_cycle();
         return execute_opcode(emulator, opcode, false);
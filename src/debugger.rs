@@ -292,7 +292,9 @@ pub fn print_debug(debug: bool, memory: &Memory, timers: &Timers, registers: &Re
         return;
     }
     steps();
-    if DEBUG_CPU {
+    if timers.is_locked {
+        println!("CPU LOCKED");
+    } else if DEBUG_CPU {
         let opcode = memory.get_byte_debug();
         print_instruction(opcode, memory);
     }
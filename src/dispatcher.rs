@@ -1,7 +1,7 @@
 use super::emulator::Emulator;
 use super::interrupts::request_interrupt;
-use super::interrupts::Interrupts;
 use super::memory::LcdMode;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
 use std::iter::FromIterator;
 
 #[allow(non_camel_case_types)]
@@ -9,7 +9,6 @@ pub enum Action {
   new_mode(LcdMode),
   request_interrupt(u8),
   ime1,
-  reload_tima(bool),
 }
 
 #[derive(Default)]
@@ -25,19 +24,6 @@ impl Dispatcher {
         Action::new_mode(mode) => ctx.memory.set_lcd_status(mode),
         Action::request_interrupt(bit) => request_interrupt(ctx, bit),
         Action::ime1 => ctx.timers.ime = true,
-        Action::reload_tima(true) => {
-          // TIMA != 0 means that tima was overwritten, reload is prevented
-          if ctx.memory.get_tima() == 0 {
-            ctx.memory.set_tima(ctx.memory.get_tma());
-            request_interrupt(ctx, Interrupts::Timer as u8);
-            ctx.dispatcher.dispatch(Action::reload_tima(false));
-            // ignores tima writes in memory
-            ctx.memory.tima_reloading = true;
-          }
-        }
-        Action::reload_tima(false) => {
-          ctx.memory.tima_reloading = false;
-        }
       }
     }
   }
@@ -45,4 +31,33 @@ impl Dispatcher {
   pub fn dispatch(&mut self, action: Action) {
     self.actions_queue.push(action);
   }
+
+  pub fn save_state(&self, state: &mut StateWriter) {
+    state.write_u32(self.actions_queue.len() as u32);
+    for action in &self.actions_queue {
+      let (tag, value) = match action {
+        Action::new_mode(mode) => (0, *mode as u8),
+        Action::request_interrupt(bit) => (1, *bit),
+        Action::ime1 => (2, 0),
+      };
+      state.write_u8(tag);
+      state.write_u8(value);
+    }
+  }
+
+  pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.actions_queue.clear();
+    for _ in 0..state.read_u32()? {
+      let tag = state.read_u8()?;
+      let value = state.read_u8()?;
+      let action = match tag {
+        0 => Action::new_mode(LcdMode::from_bits(value)),
+        1 => Action::request_interrupt(value),
+        2 => Action::ime1,
+        _ => return Err(SnapshotError::InvalidValue),
+      };
+      self.actions_queue.push(action);
+    }
+    Ok(())
+  }
 }
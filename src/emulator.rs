@@ -1,10 +1,39 @@
+use super::cartridge::{CartridgeInfo, LoadError};
 use super::constants::*;
-use super::dispatcher::Dispatcher;
+use super::cpu;
+use super::debugger::print_debug;
+use super::dispatcher::{Action, Dispatcher};
 use super::gpu;
+use super::interrupts;
+use super::interrupts::Interrupts;
+use super::joypad::Button;
 use super::memory::Memory;
+use super::movie::rom_checksum;
 use super::registers::Registers;
+use super::screenshot::{frame_hash, save_png, ScreenshotError};
+use super::serial::SerialDevice;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
 use super::timers;
 use super::timers::Timers;
+use std::fmt;
+use std::path::Path;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Event {
+  CpuLocked { opcode: u8, address: u16 },
+}
+
+impl fmt::Display for Event {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Event::CpuLocked { opcode, address } => write!(
+        f,
+        "CPU locked up by illegal opcode {:02X} at {:04X}",
+        opcode, address
+      ),
+    }
+  }
+}
 
 pub struct Emulator {
   pub background_debug: bool,
@@ -14,8 +43,15 @@ pub struct Emulator {
   pub registers: Registers,
   pub memory: Memory,
   pub timers: Timers,
+  // Last completed frame, the PPU draws into back_buffer
   pub frame_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT],
+  pub back_buffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT],
   pub dispatcher: Dispatcher,
+  pub cycles: u64,
+  pub frame_count: u64,
+  pub frame_ready: bool,
+  pub rom_checksum: u32,
+  events: Vec<Event>,
 }
 
 impl Emulator {
@@ -29,7 +65,13 @@ impl Emulator {
       memory: Memory::default(),
       timers: Timers::default(),
       frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+      back_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
       dispatcher: Dispatcher::default(),
+      cycles: 0,
+      frame_count: 0,
+      frame_ready: false,
+      rom_checksum: 0,
+      events: Vec::new(),
     }
   }
 
@@ -50,14 +92,217 @@ impl Emulator {
   }
 
   pub fn take_cycle(&mut self) {
-    Dispatcher::run(self);
-    gpu::update(self);
-    timers::update(self);
+    self.cycles += 4;
+    // The cartridge clock has its own crystal, unaffected by double speed and STOP
+    let elapsed = if self.memory.double_speed { 2 } else { 4 };
+    self.memory.cartridge.tick(elapsed);
+    // In STOP mode the PPU and the timers are frozen until a joypad line goes low
+    if self.memory.joypad.take_line_fall() {
+      self.timers.is_stopped = false;
+    }
+    if !self.timers.is_stopped {
+      Dispatcher::run(self);
+      gpu::update(self);
+      timers::update(self);
+    }
     self.memory.dma_copy_byte();
+    self.memory.tick_serial();
+  }
+
+  /// Loads a cartridge image, failing cleanly on malformed or unsupported ROMs.
+  pub fn load_rom(&mut self, buffer: Vec<u8>) -> Result<CartridgeInfo, LoadError> {
+    let checksum = rom_checksum(&buffer);
+    let info = self.memory.load_rom(buffer)?;
+    self.rom_checksum = checksum;
+    Ok(info)
+  }
+
+  /// Battery backed cartridge RAM, plus the clock for MBC3, in the .sav file layout.
+  pub fn battery_save(&self) -> Option<Vec<u8>> {
+    self.memory.cartridge.battery_save()
+  }
+
+  pub fn load_battery_save(&mut self, data: &[u8]) {
+    self.memory.cartridge.load_battery_save(data);
+  }
+
+  /// Serializes the whole machine state, the ROM itself is not included.
+  pub fn save_state(&self) -> Vec<u8> {
+    let mut state = StateWriter::default();
+    state.write_u32(self.rom_checksum);
+    state.write_u64(self.cycles);
+    state.write_u64(self.frame_count);
+    state.write_bool(self.frame_ready);
+    self.registers.save_state(&mut state);
+    self.timers.save_state(&mut state);
+    self.dispatcher.save_state(&mut state);
+    self.memory.save_state(&mut state);
+    for pixel in self.frame_buffer.iter().chain(self.back_buffer.iter()) {
+      state.write_u32(*pixel);
+    }
+    state.into_bytes()
+  }
+
+  pub fn load_state(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+    let mut state = StateReader::new(data)?;
+    if state.read_u32()? != self.rom_checksum {
+      return Err(SnapshotError::RomMismatch);
+    }
+    self.cycles = state.read_u64()?;
+    self.frame_count = state.read_u64()?;
+    self.frame_ready = state.read_bool()?;
+    self.registers.load_state(&mut state)?;
+    self.timers.load_state(&mut state)?;
+    self.dispatcher.load_state(&mut state)?;
+    self.memory.load_state(&mut state)?;
+    for pixel in self
+      .frame_buffer
+      .iter_mut()
+      .chain(self.back_buffer.iter_mut())
+    {
+      *pixel = state.read_u32()?;
+    }
+    state.finish()
+  }
+
+  /// Runs a single CPU instruction, servicing pending interrupts first.
+  pub fn step_instruction(&mut self) {
+    if interrupts::update(self) {
+      return;
+    }
+    print_debug(self.debug, &self.memory, &self.timers, &self.registers);
+    cpu::update(self);
+  }
+
+  /// Runs whole instructions until at least `cycles` T-cycles have elapsed.
+  /// Returns the number of cycles actually run.
+  pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+    let start = self.cycles;
+    while self.cycles - start < cycles {
+      self.step_instruction();
+    }
+    self.cycles - start
+  }
+
+  /// Runs until the PPU enters VBlank and returns the completed frame.
+  /// With the LCD off no VBlank happens, so this returns after one frame's worth of cycles.
+  pub fn run_frame(&mut self) -> &[u32; SCREEN_WIDTH * SCREEN_HEIGHT] {
+    let start = self.cycles;
+    self.frame_ready = false;
+    while !self.frame_ready {
+      self.step_instruction();
+      let lcd_running = self.memory.is_lcd_enabled() && !self.timers.is_stopped;
+      if !lcd_running && self.cycles - start >= DOTS_PER_FRAME as u64 {
+        // Keep counting frames so movie inputs can still wake the CPU
+        if !self.memory.is_lcd_enabled() {
+          self.frame_buffer = [LCD_OFF_COLOR; SCREEN_WIDTH * SCREEN_HEIGHT];
+          if let Some(sgb) = &mut self.memory.sgb {
+            sgb.complete_frame(false);
+          }
+        }
+        self.frame_count += 1;
+        break;
+      }
+    }
+    self.frame_ready = false;
+    &self.frame_buffer
+  }
+
+  /// Runs instructions until `predicate` holds, checking it before every instruction.
+  /// Returns the number of cycles run.
+  pub fn run_until<F: FnMut(&Emulator) -> bool>(&mut self, mut predicate: F) -> u64 {
+    let start = self.cycles;
+    while !predicate(self) {
+      self.step_instruction();
+    }
+    self.cycles - start
+  }
+
+  // Called by the PPU when VBlank starts
+  pub fn complete_frame(&mut self) {
+    if let Some(sgb) = &mut self.memory.sgb {
+      sgb.complete_frame(!self.timers.lcd_first_frame);
+    }
+    // The frame following an LCD power on is never displayed
+    if self.timers.lcd_first_frame {
+      self.timers.lcd_first_frame = false;
+      self.frame_buffer = [LCD_OFF_COLOR; SCREEN_WIDTH * SCREEN_HEIGHT];
+    } else {
+      self.frame_buffer = self.back_buffer;
+    }
+    self.frame_count += 1;
+    self.frame_ready = true;
+  }
+
+  pub fn emit(&mut self, event: Event) {
+    self.events.push(event);
+  }
+
+  /// Drains the events raised since the last call, for frontends and the debugger.
+  pub fn take_events(&mut self) -> Vec<Event> {
+    self.events.drain(..).collect()
+  }
+
+  /// Hash of the last completed frame, stable across runs and platforms.
+  pub fn frame_hash(&self) -> u64 {
+    frame_hash(&self.frame_buffer)
+  }
+
+  /// What a display shows, the SGB frame with its border when one is running.
+  /// Returns the pixels with their width and height.
+  pub fn screen(&self) -> (&[u32], usize, usize) {
+    match &self.memory.sgb {
+      Some(sgb) => (&sgb.frame, SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT),
+      None => (&self.frame_buffer, SCREEN_WIDTH, SCREEN_HEIGHT),
+    }
+  }
+
+  pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> Result<(), ScreenshotError> {
+    let (pixels, width, height) = self.screen();
+    save_png(path, pixels, width, height)
+  }
+
+  pub fn set_buttons(&mut self, buttons: u8) {
+    self.set_player_buttons(0, buttons);
+  }
+
+  /// Buttons of one of the four SGB joypads, only read by games after MLT_REQ. Other players are ignored.
+  pub fn set_player_buttons(&mut self, player: usize, buttons: u8) {
+    if self.memory.joypad.set_player_buttons(player, buttons) {
+      self
+        .dispatcher
+        .dispatch(Action::request_interrupt(Interrupts::Joypad as u8));
+    }
+  }
+
+  pub fn press(&mut self, button: Button) {
+    let buttons = self.memory.joypad.buttons() | 1 << button as u8;
+    self.set_buttons(buttons);
+  }
+
+  pub fn release(&mut self, button: Button) {
+    let buttons = self.memory.joypad.buttons() & !(1 << button as u8);
+    self.set_buttons(buttons);
+  }
+
+  pub fn press_player(&mut self, player: usize, button: Button) {
+    let buttons = self.memory.joypad.player_buttons(player) | 1 << button as u8;
+    self.set_player_buttons(player, buttons);
+  }
+
+  pub fn release_player(&mut self, player: usize, button: Button) {
+    let buttons = self.memory.joypad.player_buttons(player) & !(1 << button as u8);
+    self.set_player_buttons(player, buttons);
+  }
+
+  /// Plugs a device such as the Game Boy Printer into the link port.
+  pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+    self.memory.serial.connect(device);
   }
 
-  pub fn load_rom(&mut self, buffer: Vec<u8>) {
-    self.memory.load_rom(buffer);
+  /// Tilts MBC7 cartridges, in g with positive x to the right and positive y down.
+  pub fn set_tilt(&mut self, x: f32, y: f32) {
+    self.memory.cartridge.set_tilt(x, y);
   }
 
   pub fn mem_read(&mut self, address: u16) -> u8 {
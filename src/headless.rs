@@ -0,0 +1,60 @@
+use super::cartridge::LoadError;
+use super::constants::*;
+use super::emulator::{Emulator, Event};
+use super::movie::{Movie, MovieError, Playback};
+
+/// Runs the emulator without a window, optionally replaying an input movie.
+pub struct HeadlessRunner {
+    pub emulator: Emulator,
+    playback: Option<Playback>,
+    events: Vec<Event>,
+}
+
+impl HeadlessRunner {
+    pub fn new(rom: Vec<u8>) -> Result<Self, LoadError> {
+        let mut emulator = Emulator::default();
+        emulator.load_rom(rom)?;
+        Ok(Self {
+            emulator,
+            playback: None,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn play(&mut self, movie: Movie) -> Result<(), MovieError> {
+        let mut playback = Playback::new(movie);
+        playback.begin(&mut self.emulator)?;
+        self.playback = Some(playback);
+        Ok(())
+    }
+
+    pub fn movie_finished(&self) -> bool {
+        self.playback
+            .as_ref()
+            .is_none_or(|playback| playback.is_finished(&self.emulator))
+    }
+
+    pub fn run_frame(&mut self) -> &[u32; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        if let Some(playback) = &mut self.playback {
+            playback.apply(&mut self.emulator);
+        }
+        self.emulator.run_frame();
+        self.events.extend(self.emulator.take_events());
+        &self.emulator.frame_buffer
+    }
+
+    /// Events raised by the emulator since the runner started.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.emulator.timers.is_locked
+    }
+
+    pub fn run(&mut self, frames: u64) {
+        for _ in 0..frames {
+            self.run_frame();
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+/// Size of the picture the Game Boy Camera sensor delivers.
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// Feeds the camera sensor, one luminance byte per pixel from 0 (black) to 255 (white).
+pub trait ImageSource {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    NoFrames,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Png(e) => write!(f, "{}", e),
+            ImageError::NoFrames => write!(f, "no PNG frames found"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(e: std::io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(e: png::DecodingError) -> Self {
+        ImageError::Png(e)
+    }
+}
+
+// Decodes any PNG to luminance and scales it to the sensor size
+pub fn load_png_luminance<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, ImageError> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+    let (color_type, _) = reader.output_color_type();
+    let samples = color_type.samples();
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let luminance = |x: usize, y: usize| {
+        let pixel = &buffer[y * info.line_size + x * samples..];
+        match samples {
+            1 | 2 => pixel[0],
+            _ => ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000)
+                as u8,
+        }
+    };
+    let mut pixels = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+    for y in 0..CAMERA_HEIGHT {
+        for x in 0..CAMERA_WIDTH {
+            pixels.push(luminance(x * width / CAMERA_WIDTH, y * height / CAMERA_HEIGHT));
+        }
+    }
+    Ok(pixels)
+}
+
+/// Horizontal gradient crossed by a checkerboard, used when no image is given.
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let gradient = (x * 255 / (CAMERA_WIDTH - 1)) as u8;
+                let checker = (x / 16 + y / 16) % 2 == 0;
+                pixels.push(if checker { gradient } else { 255 - gradient });
+            }
+        }
+        pixels
+    }
+}
+
+/// Same picture on every capture.
+pub struct StaticImage {
+    pixels: Vec<u8>,
+}
+
+impl StaticImage {
+    pub fn new(pixels: Vec<u8>) -> Self {
+        Self { pixels }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        Ok(Self::new(load_png_luminance(path)?))
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+/// Cycles through the PNG files of a directory in name order, one per capture.
+pub struct ImageSequence {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl ImageSequence {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let mut paths = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension() == Some("png".as_ref()))
+            .collect::<Vec<_>>();
+        paths.sort();
+        let frames = paths
+            .iter()
+            .map(load_png_luminance)
+            .collect::<Result<Vec<_>, _>>()?;
+        if frames.is_empty() {
+            return Err(ImageError::NoFrames);
+        }
+        Ok(Self { frames, next: 0 })
+    }
+}
+
+impl ImageSource for ImageSequence {
+    fn capture(&mut self) -> Vec<u8> {
+        let frame = self.frames[self.next].clone();
+        self.next = (self.next + 1) % self.frames.len();
+        frame
+    }
+}
+
+/// Picks a sequence for directories and a static image for files.
+pub fn load_image_source<P: AsRef<Path>>(path: P) -> Result<Box<dyn ImageSource>, ImageError> {
+    if path.as_ref().is_dir() {
+        Ok(Box::new(ImageSequence::load(path)?))
+    } else {
+        Ok(Box::new(StaticImage::load(path)?))
+    }
+}
@@ -2,29 +2,6 @@ use super::emulator::Emulator;
 use super::utils::get_bit_at;
 use super::utils::*;
 
-#[derive(PartialEq, Debug)]
-pub enum StatCond {
-    HBLANK,
-    VBlank,
-    OAM,
-    LYC,
-    None,
-}
-
-impl StatCond {
-    pub fn or(stat1: StatCond, stat2: StatCond) -> StatCond {
-        if stat1 == StatCond::None {
-            stat2
-        } else {
-            stat1
-        }
-    }
-
-    pub fn is_stat(&self) -> bool {
-        self != &StatCond::None
-    }
-}
-
 pub enum Interrupts {
     VBlank = 0,
     LCDStat = 1,
@@ -33,23 +10,39 @@ pub enum Interrupts {
     Joypad = 4,
 }
 
-pub fn update(ctx: &mut Emulator) {
-    let i_f = ctx.memory.read(0xff0f);
-    let i_e = ctx.memory.read(0xffff);
-    if i_f > 0 {
-        for bit in 0..5 {
-            if get_bit_at(i_f, bit) && get_bit_at(i_e, bit) {
-                if ctx.timers.is_halted && !ctx.timers.ime {
-                    ctx.timers.halt_bug = true;
-                }
-                ctx.timers.is_halted = false;
-                if ctx.timers.ime {
-                    ctx.timers.clear_ime();
-                    let cancelled = interrupt_execution(ctx, bit);
-                    if !cancelled {
-                        break;
-                    }
-                }
+pub fn pending(ctx: &Emulator) -> u8 {
+    ctx.memory.read(0xff0f) & ctx.memory.read(0xffff) & 0b0001_1111
+}
+
+/// Services pending interrupts and wakes the CPU from HALT.
+/// Returns true when the step was spent leaving HALT, so no opcode is fetched.
+pub fn update(ctx: &mut Emulator) -> bool {
+    if ctx.timers.is_locked || ctx.timers.is_stopped || pending(ctx) == 0 {
+        return false;
+    }
+    if ctx.timers.is_halted {
+        // Leaving HALT takes one M-cycle, the interrupt is only serviced with IME=1
+        ctx.timers.is_halted = false;
+        ctx.take_cycle();
+        if ctx.timers.ime {
+            dispatch(ctx);
+        }
+        return true;
+    }
+    if ctx.timers.ime {
+        dispatch(ctx);
+    }
+    false
+}
+
+fn dispatch(ctx: &mut Emulator) {
+    let pending = pending(ctx);
+    for bit in 0..5 {
+        if get_bit_at(pending, bit) {
+            ctx.timers.clear_ime();
+            let cancelled = interrupt_execution(ctx, bit);
+            if !cancelled {
+                break;
             }
         }
     }
@@ -83,25 +76,8 @@ pub fn interrupt_execution(ctx: &mut Emulator, interrupt: u8) -> bool {
     }
 }
 
-pub fn stat_irq(ctx: &Emulator, stat: StatCond) -> StatCond {
-    let bit = match stat {
-        StatCond::HBLANK => 3,
-        StatCond::VBlank => 4,
-        StatCond::OAM => 5,
-        StatCond::LYC => 6,
-        _ => unreachable!(),
-    };
-    let lcd_status = ctx.memory.read(0xff41);
-    if get_bit_at(lcd_status, bit) {
-        stat
-    } else {
-        StatCond::None
-    }
-}
-
 pub fn request_interrupt(ctx: &mut Emulator, bit: u8) {
     let interrupt_flags = ctx.memory.read(0xff0f);
     let modified_flag = set_bit_at(interrupt_flags, bit);
     ctx.memory.write(0xff0f, modified_flag);
-    ctx.timers.is_halted = false;
 }
@@ -0,0 +1,152 @@
+use super::memory::Model;
+
+/// How the CPU sees one register of the 0xff00-0xff7f IO page.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct IoRegister {
+    // Bits that always read back as 1, either unused or write-only
+    pub unreadable: u8,
+    // Bits the CPU is allowed to change
+    pub writable: u8,
+    pub cgb_only: bool,
+    // Bits that only exist on CGB, they act as unreadable and read-only on DMG
+    pub cgb_bits: u8,
+}
+
+const UNMAPPED: IoRegister = IoRegister {
+    unreadable: 0xff,
+    writable: 0x00,
+    cgb_only: false,
+    cgb_bits: 0x00,
+};
+
+const fn reg(unreadable: u8, writable: u8) -> IoRegister {
+    IoRegister {
+        unreadable,
+        writable,
+        cgb_only: false,
+        cgb_bits: 0x00,
+    }
+}
+
+const fn cgb(unreadable: u8, writable: u8) -> IoRegister {
+    IoRegister {
+        unreadable,
+        writable,
+        cgb_only: true,
+        cgb_bits: 0x00,
+    }
+}
+
+const fn with_cgb_bits(register: IoRegister, cgb_bits: u8) -> IoRegister {
+    IoRegister {
+        cgb_bits,
+        ..register
+    }
+}
+
+/// Looks up the register mapped at `address`, unmapped addresses read 0xff and ignore writes.
+pub fn io_register(address: u16) -> IoRegister {
+    match address {
+        0xff00 => reg(0xc0, 0x30), // P1
+        0xff01 => reg(0x00, 0xff), // SB
+        0xff02 => with_cgb_bits(reg(0x7e, 0x81), 0x02), // SC, bit 1 is the CGB fast clock
+        0xff04 => reg(0x00, 0xff), // DIV
+        0xff05 => reg(0x00, 0xff), // TIMA
+        0xff06 => reg(0x00, 0xff), // TMA
+        0xff07 => reg(0xf8, 0x07), // TAC
+        0xff0f => reg(0xe0, 0x1f), // IF
+        0xff10 => reg(0x80, 0x7f), // NR10
+        0xff11 => reg(0x3f, 0xff), // NR11
+        0xff12 => reg(0x00, 0xff), // NR12
+        0xff13 => reg(0xff, 0xff), // NR13
+        0xff14 => reg(0xbf, 0xc7), // NR14
+        0xff16 => reg(0x3f, 0xff), // NR21
+        0xff17 => reg(0x00, 0xff), // NR22
+        0xff18 => reg(0xff, 0xff), // NR23
+        0xff19 => reg(0xbf, 0xc7), // NR24
+        0xff1a => reg(0x7f, 0x80), // NR30
+        0xff1b => reg(0xff, 0xff), // NR31
+        0xff1c => reg(0x9f, 0x60), // NR32
+        0xff1d => reg(0xff, 0xff), // NR33
+        0xff1e => reg(0xbf, 0xc7), // NR34
+        0xff20 => reg(0xff, 0x3f), // NR41
+        0xff21 => reg(0x00, 0xff), // NR42
+        0xff22 => reg(0x00, 0xff), // NR43
+        0xff23 => reg(0xbf, 0xc0), // NR44
+        0xff24 => reg(0x00, 0xff), // NR50
+        0xff25 => reg(0x00, 0xff), // NR51
+        0xff26 => reg(0x70, 0x80), // NR52
+        0xff30..=0xff3f => reg(0x00, 0xff), // Wave RAM
+        0xff40 => reg(0x00, 0xff), // LCDC
+        0xff41 => reg(0x80, 0x78), // STAT
+        0xff42 => reg(0x00, 0xff), // SCY
+        0xff43 => reg(0x00, 0xff), // SCX
+        0xff44 => reg(0x00, 0x00), // LY
+        0xff45 => reg(0x00, 0xff), // LYC
+        0xff46 => reg(0x00, 0xff), // DMA
+        0xff47 => reg(0x00, 0xff), // BGP
+        0xff48 => reg(0x00, 0xff), // OBP0
+        0xff49 => reg(0x00, 0xff), // OBP1
+        0xff4a => reg(0x00, 0xff), // WY
+        0xff4b => reg(0x00, 0xff), // WX
+        0xff4d => cgb(0x7e, 0x01), // KEY1
+        0xff4f => cgb(0xfe, 0x01), // VBK
+        0xff51..=0xff54 => cgb(0xff, 0xff), // HDMA1-4
+        0xff55 => cgb(0x00, 0xff), // HDMA5
+        0xff56 => cgb(0x3c, 0xc3), // RP
+        0xff68 => cgb(0x40, 0xbf), // BCPS
+        0xff69 => cgb(0x00, 0xff), // BCPD
+        0xff6a => cgb(0x40, 0xbf), // OCPS
+        0xff6b => cgb(0x00, 0xff), // OCPD
+        0xff6c => cgb(0xfe, 0x01), // OPRI
+        0xff70 => cgb(0xf8, 0x07), // SVBK
+        _ => UNMAPPED,
+    }
+}
+
+impl IoRegister {
+    pub fn is_mapped(&self, model: Model) -> bool {
+        *self != UNMAPPED && (!self.cgb_only || model == Model::Cgb)
+    }
+
+    /// Value the CPU reads given the stored register contents.
+    pub fn read(&self, model: Model, value: u8) -> u8 {
+        if !self.is_mapped(model) {
+            return 0xff;
+        }
+        value | (self.unreadable & !self.cgb_bits(model))
+    }
+
+    /// New register contents after a CPU write, read-only bits keep their old value.
+    pub fn write(&self, model: Model, old: u8, data: u8) -> u8 {
+        if !self.is_mapped(model) {
+            return old;
+        }
+        let writable = self.writable | self.cgb_bits(model);
+        (old & !writable) | (data & writable)
+    }
+
+    fn cgb_bits(&self, model: Model) -> u8 {
+        if model == Model::Cgb {
+            self.cgb_bits
+        } else {
+            0
+        }
+    }
+}
+
+#[test]
+fn test_io_register_masks() {
+    let nr52 = io_register(0xff26);
+    assert_eq!(nr52.read(Model::Dmg, 0x80), 0xf0);
+    assert_eq!(nr52.write(Model::Dmg, 0x01, 0x00), 0x01);
+    assert_eq!(io_register(0xff03).read(Model::Dmg, 0x12), 0xff);
+    assert_eq!(io_register(0xff4f).read(Model::Dmg, 0x00), 0xff);
+    assert_eq!(io_register(0xff4f).read(Model::Cgb, 0x01), 0xff);
+    assert_eq!(io_register(0xff70).write(Model::Dmg, 0x00, 0x07), 0x00);
+    assert_eq!(io_register(0xff70).write(Model::Cgb, 0x00, 0xff), 0x07);
+    assert_eq!(io_register(0xff02).write(Model::Dmg, 0x00, 0x83), 0x81);
+    assert_eq!(io_register(0xff02).read(Model::Dmg, 0x81), 0xff);
+    assert_eq!(io_register(0xff02).write(Model::Cgb, 0x00, 0x83), 0x83);
+    assert_eq!(io_register(0xff02).read(Model::Cgb, 0x01), 0x7d);
+}
@@ -1,31 +1,187 @@
-use super::constants::*;
-use super::dispatcher::Action;
 use super::emulator::Emulator;
-use super::interrupts::Interrupts;
-use minifb::{Key, Window};
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
+use minifb::{Key, KeyRepeat, Window};
 
-pub fn update(ctx: &mut Emulator, window: &Window) {
-  let p1 = !ctx.memory.read(P1_JOYPAD_ADDRESS) & 0b0011_1111;
-  match p1 {
-    0b10_0000 if window.is_key_down(Key::Down) => request_joypad_interrupt(ctx, p1, 0b1000),
-    0b10_0000 if window.is_key_down(Key::Up) => request_joypad_interrupt(ctx, p1, 0b0100),
-    0b10_0000 if window.is_key_down(Key::Left) => request_joypad_interrupt(ctx, p1, 0b0010),
-    0b10_0000 if window.is_key_down(Key::Right) => request_joypad_interrupt(ctx, p1, 0b0001),
-    0b01_0000 if window.is_key_down(Key::Enter) => request_joypad_interrupt(ctx, p1, 0b1000),
-    0b01_0000 if window.is_key_down(Key::Space) => request_joypad_interrupt(ctx, p1, 0b0100),
-    0b01_0000 if window.is_key_down(Key::Z) => request_joypad_interrupt(ctx, p1, 0b0010),
-    0b01_0000 if window.is_key_down(Key::X) => request_joypad_interrupt(ctx, p1, 0b0001),
-    _ if window.is_key_down(Key::P) => ctx.debug(),
-    _ if window.is_key_down(Key::B) => ctx.toggle_background(),
-    _ if window.is_key_down(Key::S) => ctx.toggle_sprites(),
-    _ if window.is_key_down(Key::W) => ctx.toggle_window(),
-    _ => {}
-  };
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Button {
+  Right = 0,
+  Left = 1,
+  Up = 2,
+  Down = 3,
+  A = 4,
+  B = 5,
+  Select = 6,
+  Start = 7,
+}
+
+// SGB multiplayer supports up to four joypads
+pub const MAX_PLAYERS: usize = 4;
+
+pub struct Joypad {
+  select: u8,
+  buttons: [u8; MAX_PLAYERS],
+  // Joypad read through P1 and how many are connected, changed by SGB MLT_REQ
+  player: u8,
+  players: u8,
+  // An input line went from high to low since the last take_line_fall, wakes the CPU from STOP
+  line_fell: bool,
+}
+
+impl Joypad {
+  pub fn default() -> Self {
+    Self {
+      select: 0b0011_0000,
+      buttons: [0; MAX_PLAYERS],
+      player: 0,
+      players: 1,
+      line_fell: false,
+    }
+  }
+
+  // P10-P13 are active low, a line goes low when its button is pressed on a selected row.
+  // With no row selected the SGB puts the ID of the current joypad on the lines.
+  pub fn lines(&self) -> u8 {
+    let buttons = self.buttons[self.player as usize];
+    let mut lines = 0;
+    if self.select & 0b0001_0000 == 0 {
+      lines |= buttons & 0x0f;
+    }
+    if self.select & 0b0010_0000 == 0 {
+      lines |= buttons >> 4;
+    }
+    if self.select == 0b0011_0000 {
+      lines = self.player;
+    }
+    !lines & 0x0f
+  }
+
+  pub fn read(&self) -> u8 {
+    0b1100_0000 | self.select | self.lines()
+  }
+
+  // Returns true when any input line goes from high to low (joypad interrupt)
+  pub fn write(&mut self, data: u8) -> bool {
+    let prev_lines = self.lines();
+    let select = data & 0b0011_0000;
+    // Releasing P15 moves on to the next joypad
+    if self.players > 1 && self.select & 0b0010_0000 == 0 && select & 0b0010_0000 != 0 {
+      self.player = (self.player + 1) % self.players;
+    }
+    self.select = select;
+    self.detect_line_fall(prev_lines)
+  }
+
+  pub fn buttons(&self) -> u8 {
+    self.buttons[0]
+  }
+
+  pub fn all_buttons(&self) -> [u8; MAX_PLAYERS] {
+    self.buttons
+  }
+
+  pub fn player_buttons(&self, player: usize) -> u8 {
+    self.buttons.get(player).copied().unwrap_or(0)
+  }
+
+  // Returns true when any input line goes from high to low (joypad interrupt)
+  pub fn set_buttons(&mut self, buttons: u8) -> bool {
+    self.set_player_buttons(0, buttons)
+  }
+
+  // Players past MAX_PLAYERS do not exist, their buttons are ignored
+  pub fn set_player_buttons(&mut self, player: usize, buttons: u8) -> bool {
+    if player >= MAX_PLAYERS {
+      return false;
+    }
+    let prev_lines = self.lines();
+    self.buttons[player] = buttons;
+    self.detect_line_fall(prev_lines)
+  }
+
+  fn detect_line_fall(&mut self, prev_lines: u8) -> bool {
+    let fell = prev_lines & !self.lines() != 0;
+    self.line_fell |= fell;
+    fell
+  }
+
+  pub fn take_line_fall(&mut self) -> bool {
+    std::mem::replace(&mut self.line_fell, false)
+  }
+
+  pub fn players(&self) -> u8 {
+    self.players
+  }
+
+  // Reading starts over from the first joypad when the count changes
+  pub fn set_players(&mut self, players: u8) {
+    if players != self.players {
+      self.players = players;
+      self.player = 0;
+    }
+  }
+
+  pub fn save_state(&self, state: &mut StateWriter) {
+    state.write_u8(self.select);
+    state.write_bytes(&self.buttons);
+    state.write_u8(self.player);
+    state.write_u8(self.players);
+    state.write_bool(self.line_fell);
+  }
+
+  pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.select = state.read_u8()?;
+    state.read_into(&mut self.buttons)?;
+    self.player = state.read_u8()?;
+    self.players = state.read_u8()?;
+    self.line_fell = state.read_bool()?;
+    if self.players == 0 || self.players as usize > MAX_PLAYERS || self.player >= self.players {
+      return Err(SnapshotError::InvalidValue);
+    }
+    Ok(())
+  }
 }
 
-fn request_joypad_interrupt(ctx: &mut Emulator, p1: u8, joypad_reg: u8) {
-  ctx.memory.write(P1_JOYPAD_ADDRESS, p1 | joypad_reg);
-  ctx
-    .dispatcher
-    .dispatch(Action::request_interrupt(Interrupts::Joypad as u8));
+pub fn update(ctx: &mut Emulator, window: &Window) {
+  let layouts = [
+    [
+      (Key::Right, Button::Right),
+      (Key::Left, Button::Left),
+      (Key::Up, Button::Up),
+      (Key::Down, Button::Down),
+      (Key::X, Button::A),
+      (Key::Z, Button::B),
+      (Key::Space, Button::Select),
+      (Key::Enter, Button::Start),
+    ],
+    [
+      (Key::L, Button::Right),
+      (Key::J, Button::Left),
+      (Key::I, Button::Up),
+      (Key::K, Button::Down),
+      (Key::O, Button::A),
+      (Key::U, Button::B),
+      (Key::N, Button::Select),
+      (Key::M, Button::Start),
+    ],
+  ];
+  for (player, keys) in layouts.iter().enumerate() {
+    let buttons = keys
+      .iter()
+      .filter(|(key, _)| window.is_key_down(*key))
+      .fold(0, |buttons, (_, button)| buttons | 1 << *button as u8);
+    ctx.set_player_buttons(player, buttons);
+  }
+
+  if window.is_key_pressed(Key::P, KeyRepeat::No) {
+    ctx.debug();
+  }
+  if window.is_key_pressed(Key::B, KeyRepeat::No) {
+    ctx.toggle_background();
+  }
+  if window.is_key_pressed(Key::S, KeyRepeat::No) {
+    ctx.toggle_sprites();
+  }
+  if window.is_key_pressed(Key::W, KeyRepeat::No) {
+    ctx.toggle_window();
+  }
 }
@@ -1,15 +1,26 @@
 pub mod alu;
 pub mod cartridge;
+pub mod clock;
 pub mod constants;
 pub mod cpu;
 pub mod debugger;
 pub mod dispatcher;
 pub mod emulator;
 pub mod gpu;
+pub mod headless;
+pub mod image_source;
 pub mod interrupts;
+pub mod io;
 pub mod joypad;
 pub mod memory;
+pub mod movie;
+pub mod pacer;
+pub mod printer;
 pub mod ppu;
 pub mod registers;
+pub mod screenshot;
+pub mod serial;
+pub mod sgb;
+pub mod snapshot;
 pub mod timers;
 pub mod utils;
@@ -1,61 +1,313 @@
-use minifb::{Key, Scale, Window, WindowOptions};
-use soup_gb::constants::*;
-use soup_gb::cpu;
-use soup_gb::debugger::print_debug;
+use minifb::{Key, KeyRepeat, MouseMode, Scale, Window, WindowOptions};
+use soup_gb::cartridge::header::RomHeader;
+use soup_gb::cartridge::LoadError;
+use soup_gb::clock::{Clock, SystemClock};
 use soup_gb::emulator::Emulator;
-use soup_gb::interrupts;
+use soup_gb::headless::HeadlessRunner;
+use soup_gb::image_source::load_image_source;
 use soup_gb::joypad;
-use soup_gb::memory::LcdMode;
-use std::fs::File;
-use std::io::Read;
+use soup_gb::movie::{Movie, Playback, StartState};
+use soup_gb::pacer::FramePacer;
+use soup_gb::printer::Printer;
+use std::path::Path;
+use std::str::FromStr;
 use std::time::Instant;
 
-pub fn main() {
+fn option_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    let position = args.iter().position(|arg| arg == flag)?;
+    match args.get(position + 1) {
+        Some(value) => Some(value),
+        None => {
+            println!("{} expects a value", flag);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_option<T: FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let value = option_value(args, flag)?;
+    match value.parse::<T>() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            println!("{} expects a number", flag);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_movie(file_path: &str) -> Movie {
+    Movie::load(file_path).unwrap_or_else(|e| {
+        println!("Unable to load movie {}: {}", file_path, e);
+        std::process::exit(1);
+    })
+}
+
+fn rom_path(args: &[String]) -> &String {
+    match args.last() {
+        Some(file_path) => file_path,
+        None => {
+            println!("Usage: soup_gb [headless|info] [options] rom.gb");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn read_rom(file_path: &str) -> Vec<u8> {
+    std::fs::read(file_path).unwrap_or_else(|e| {
+        println!("Unable to read {}: {}", file_path, e);
+        std::process::exit(1);
+    })
+}
+
+fn exit_on_load_error(file_path: &str, e: LoadError) -> ! {
+    println!("Unable to load {}: {}", file_path, e);
+    std::process::exit(1);
+}
+
+fn screenshot_path(rom_path: &str, frame: u64) -> String {
+    let path = Path::new(rom_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_{}.png", stem, frame))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn save_path(rom_path: &str) -> std::path::PathBuf {
+    Path::new(rom_path).with_extension("sav")
+}
+
+fn write_battery_save(emulator: &Emulator, rom_path: &str) {
+    if let Some(data) = emulator.battery_save() {
+        match std::fs::write(save_path(rom_path), data) {
+            Ok(_) => println!("Game saved"),
+            Err(e) => println!("Unable to save game: {}", e),
+        }
+    }
+}
+
+// Feeds the Pocket Camera from a PNG or a directory of PNG frames
+fn set_camera_source(emulator: &mut Emulator, args: &[String]) {
+    if let Some(path) = option_value(args, "--camera") {
+        match load_image_source(path) {
+            Ok(source) => emulator.memory.cartridge.set_image_source(source),
+            Err(e) => {
+                println!("Unable to load camera image {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Prints land as PNG files in the given directory
+fn connect_printer(emulator: &mut Emulator, args: &[String]) {
+    if let Some(path) = option_value(args, "--printer") {
+        if let Err(e) = std::fs::create_dir_all(path) {
+            println!("Unable to create printer directory {}: {}", path, e);
+            std::process::exit(1);
+        }
+        emulator.connect_serial(Box::new(Printer::new(path)));
+    }
+}
+
+// The mouse position inside the window tilts MBC7 carts, the center is flat
+fn update_tilt(emulator: &mut Emulator, window: &Window) {
+    match window.get_mouse_pos(MouseMode::Discard) {
+        Some((x, y)) => {
+            let (width, height) = window.get_size();
+            let tilt = |position: f32, size: usize| position / size.max(1) as f32 * 2.0 - 1.0;
+            emulator.set_tilt(tilt(x, width), tilt(y, height));
+        }
+        None => emulator.set_tilt(0.0, 0.0),
+    }
+}
+
+fn save_screenshot(emulator: &Emulator, path: &str) {
+    match emulator.save_screenshot(path) {
+        Ok(_) => println!("Screenshot saved to {}", path),
+        Err(e) => println!("Unable to save screenshot: {}", e),
+    }
+}
+
+fn run_headless(args: &[String]) {
+    let file_path = rom_path(args);
+    let mut runner = HeadlessRunner::new(read_rom(file_path))
+        .unwrap_or_else(|e| exit_on_load_error(file_path, e));
+    set_camera_source(&mut runner.emulator, args);
+    connect_printer(&mut runner.emulator, args);
+    let playing = match option_value(args, "--play") {
+        Some(movie_path) => {
+            if let Err(e) = runner.play(load_movie(movie_path)) {
+                println!("Unable to play movie {}: {}", movie_path, e);
+                std::process::exit(1);
+            }
+            true
+        }
+        None => false,
+    };
+    let screenshot_frame: Option<u64> = parse_option(args, "--screenshot-at-frame");
+    let frames: Option<u64> = parse_option(args, "--frames").or(screenshot_frame);
+    if frames.is_none() && !playing {
+        println!("headless expects --frames, --screenshot-at-frame or --play");
+        std::process::exit(1);
+    }
+
+    loop {
+        let frame_count = runner.emulator.frame_count;
+        if Some(frame_count) == screenshot_frame {
+            let path = option_value(args, "--screenshot")
+                .cloned()
+                .unwrap_or_else(|| screenshot_path(file_path, frame_count));
+            save_screenshot(&runner.emulator, &path);
+        }
+        let done = match frames {
+            Some(frames) => frame_count >= frames,
+            None => runner.movie_finished(),
+        };
+        if done {
+            break;
+        }
+        runner.run_frame();
+    }
+    println!("Ran {} frames", runner.emulator.frame_count);
+    println!("Frame hash: {:016x}", runner.emulator.frame_hash());
+    for event in runner.events() {
+        println!("{}", event);
+    }
+    if runner.is_locked() {
+        std::process::exit(2);
+    }
+}
+
+fn run_info(args: &[String]) {
+    let file_path = rom_path(args);
+    match RomHeader::parse(&read_rom(file_path)) {
+        Ok(header) => println!("{}", header),
+        Err(e) => exit_on_load_error(file_path, e),
+    }
+}
+
+fn run_desktop(args: &[String]) {
     let mut emulator = Emulator::default();
-    let mut args: Vec<String> = std::env::args().collect();
-    let file_path = args.pop().unwrap();
-    let mut rom = File::open(file_path.clone()).unwrap();
-    let mut buffer = Vec::new();
-    rom.read_to_end(&mut buffer).unwrap();
-    emulator.load_rom(buffer);
+    let file_path = rom_path(args);
+    match emulator.load_rom(read_rom(file_path)) {
+        Ok(info) => println!("Loaded {}", info),
+        Err(e) => exit_on_load_error(file_path, e),
+    }
+    set_camera_source(&mut emulator, args);
+    connect_printer(&mut emulator, args);
+    // Movies always start from a blank cartridge
+    let uses_movie =
+        option_value(args, "--record").is_some() || option_value(args, "--play").is_some();
+    if !uses_movie {
+        if let Ok(data) = std::fs::read(save_path(file_path)) {
+            emulator.load_battery_save(&data);
+            println!("Loaded save");
+        }
+    }
+
+    let mut pacer = FramePacer::default();
+    if let Some(multiplier) = parse_option(args, "--fast-forward") {
+        pacer.set_fast_forward_multiplier(multiplier);
+    }
+    if let Some(multiplier) = parse_option(args, "--slow-motion") {
+        pacer.set_slow_motion_multiplier(multiplier);
+    }
+
+    let record_path = option_value(args, "--record");
+    let mut recording = record_path
+        .map(|_| Movie::record(&mut emulator, StartState::PowerOn, SystemClock.now()));
+    let mut playback = option_value(args, "--play").map(|movie_path| {
+        let mut playback = Playback::new(load_movie(movie_path));
+        if let Err(e) = playback.begin(&mut emulator) {
+            println!("Unable to play movie {}: {}", movie_path, e);
+            std::process::exit(1);
+        }
+        playback
+    });
 
     let windows_options = WindowOptions {
         scale: Scale::X2,
         ..WindowOptions::default()
     };
 
-    let mut window = Window::new(&file_path, SCREEN_WIDTH, SCREEN_HEIGHT, windows_options)
+    let (_, width, height) = emulator.screen();
+    let mut window = Window::new(file_path, width, height, windows_options)
         .unwrap_or_else(|e| {
             panic!("{}", e);
         });
 
-    // window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+    // Timing is driven by the frame pacer
+    window.limit_update_rate(None);
     let mut frame_time = Instant::now();
     let mut frame_counter = 0;
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        interrupts::update(&mut emulator);
-        print_debug(
-            emulator.debug,
-            &emulator.memory,
-            &emulator.timers,
-            &emulator.registers,
-        );
-        cpu::update(&mut emulator);
-        joypad::update(&mut emulator, &window);
-        if emulator.memory.get_ly() == 0x90 && emulator.memory.lcd_mode() == LcdMode::HBlank {
-            match window.update_with_buffer(&emulator.frame_buffer, SCREEN_WIDTH, SCREEN_HEIGHT) {
+        match &mut playback {
+            Some(playback) => playback.apply(&mut emulator),
+            None => {
+                joypad::update(&mut emulator, &window);
+                // Movies only carry the joypad, so tilt stays flat while recording
+                if recording.is_none() {
+                    update_tilt(&mut emulator, &window);
+                }
+            }
+        }
+        if let Some(movie) = &mut recording {
+            movie.record_input(emulator.frame_count, emulator.memory.joypad.all_buttons());
+        }
+        emulator.run_frame();
+        for event in emulator.take_events() {
+            println!("{}", event);
+        }
+        if pacer.should_present() {
+            let (pixels, width, height) = emulator.screen();
+            match window.update_with_buffer(pixels, width, height) {
                 Ok(_) => {}
                 Err(e) => {
                     println!("{}", e);
                     std::process::exit(0);
                 }
             }
-            if frame_time.elapsed().as_millis() >= 1000 {
-                window.set_title(&format!("FPS: {}", frame_counter));
-                frame_time = Instant::now();
-                frame_counter = 0;
-            }
-            frame_counter += 1
+        } else {
+            window.update();
+        }
+        if window.is_key_pressed(Key::Tab, KeyRepeat::No) {
+            pacer.toggle_fast_forward();
+        }
+        if window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            pacer.toggle_slow_motion();
+        }
+        if window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            pacer.toggle_uncapped();
         }
+        if window.is_key_pressed(Key::F12, KeyRepeat::No) {
+            save_screenshot(&emulator, &screenshot_path(file_path, emulator.frame_count));
+        }
+        pacer.wait();
+        if frame_time.elapsed().as_millis() >= 1000 {
+            window.set_title(&format!("FPS: {} ({:?})", frame_counter, pacer.pace()));
+            frame_time = Instant::now();
+            frame_counter = 0;
+        }
+        frame_counter += 1
+    }
+
+    if !uses_movie {
+        write_battery_save(&emulator, file_path);
+    }
+    if let (Some(mut movie), Some(movie_path)) = (recording, record_path) {
+        movie.finish(emulator.frame_count);
+        match movie.save(movie_path) {
+            Ok(_) => println!("Movie saved"),
+            Err(e) => println!("Unable to save movie: {}", e),
+        }
+    }
+}
+
+pub fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|arg| arg.as_str()) {
+        Some("headless") => run_headless(&args[1..]),
+        Some("info") => run_info(&args[1..]),
+        _ => run_desktop(&args),
     }
 }
@@ -1,13 +1,25 @@
+use super::cartridge::camera::PocketCamera;
+use super::cartridge::huc1::HuC1;
+use super::cartridge::huc3::HuC3;
 use super::cartridge::mbc1::MBC1;
 use super::cartridge::mbc2::MBC2;
 use super::cartridge::mbc3::MBC3;
+use super::cartridge::mbc7::MBC7;
+use super::cartridge::mmm01::{menu_header_offset, MMM01};
 use super::cartridge::rom_only::RomOnly;
-use super::cartridge::Cartridge;
+use super::cartridge::tama5::TAMA5;
+use super::cartridge::{Cartridge, CartridgeInfo, LoadError};
 use super::constants::*;
+use super::interrupts::Interrupts;
+use super::io::io_register;
+use super::joypad::Joypad;
+use super::serial::Serial;
+use super::sgb::Sgb;
+use super::timers::Timer;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
 use super::utils::{clear_bit_at, get_bit_at, set_bit_at};
 use byteorder::{BigEndian, ByteOrder};
 use std::fmt;
-use std::io::Write;
 
 pub struct Point2D {
     pub x: u8,
@@ -22,16 +34,35 @@ pub enum LcdMode {
     ReadVRAM,
 }
 
-#[derive(PartialEq)]
-pub enum PrevStatCond {
-    VBlank,
-    LYC(u8),
-    HBLANK(u8),
-    OAM,
+impl LcdMode {
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0x3 {
+            0x0 => LcdMode::HBlank,
+            0x1 => LcdMode::VBlank,
+            0x2 => LcdMode::ReadOAM,
+            0x3 => LcdMode::ReadVRAM,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Model {
+    Dmg,
+    Cgb,
 }
 
 pub struct Memory {
     pub cartridge: Box<dyn Cartridge>,
+    pub joypad: Joypad,
+    pub timer: Timer,
+    pub serial: Serial,
+    pub model: Model,
+    // Present while a cartridge with SGB functions runs on a DMG
+    pub sgb: Option<Sgb>,
+    // KEY1 bit 7 and bit 0, the switch happens on the next STOP
+    pub double_speed: bool,
+    pub speed_switch_armed: bool,
     wram: [u8; 0x2000],
     vram: [u8; 0x2000],
     echo: [u8; 0x1e00],
@@ -42,22 +73,22 @@ pub struct Memory {
     wram_bank: u8,
     pub stack_pointer: u16,
     program_counter: u16,
-    dma_copy_address: u16,
-    dma_copy_in_progress: bool,
+    dma_source: u16,
     dma_cursor: u16,
-    pub prev_timer_bit: u16,
-    pub tima_reloading: bool,
-    pub prev_stat_condition: PrevStatCond,
+    dma_active: bool,
+    // Byte on the DMA source bus, seen by the CPU on conflicting reads
+    dma_byte: u8,
+    // M-cycles until a requested transfer starts, the previous one keeps running meanwhile
+    dma_delay: u8,
+    dma_next_source: u16,
+    // Internal STAT interrupt line, IF is only set on its rising edge
+    pub stat_line: bool,
 }
 
 // General Initialization functions
 impl Memory {
     pub fn default() -> Self {
         let mut io_ports = [0; 0x80];
-        // DIV & TIMA
-        io_ports[0x04] = 0xab;
-        io_ports[0x05] = 0xcc;
-
         io_ports[0x10] = 0x80;
         io_ports[0x11] = 0xBF;
         io_ports[0x12] = 0xF3;
@@ -80,6 +111,13 @@ impl Memory {
 
         Self {
             cartridge: Box::new(RomOnly::default()),
+            joypad: Joypad::default(),
+            timer: Timer::default(),
+            serial: Serial::default(),
+            model: Model::Dmg,
+            sgb: None,
+            double_speed: false,
+            speed_switch_armed: false,
             wram: [0; 0x2000],
             vram: [0; 0x2000],
             echo: [0; 0x1e00],
@@ -90,14 +128,88 @@ impl Memory {
             io_ports,
             stack_pointer: 0xfffe,
             program_counter: 0x100,
-            dma_copy_address: 0,
-            dma_copy_in_progress: false,
+            dma_source: 0,
             dma_cursor: 0,
-            prev_timer_bit: 0,
-            tima_reloading: false,
-            prev_stat_condition: PrevStatCond::OAM, // everything following oam recognized.
+            dma_active: false,
+            dma_byte: 0xff,
+            dma_delay: 0,
+            dma_next_source: 0,
+            stat_line: false,
+        }
+    }
+}
+
+// Snapshot functions
+impl Memory {
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.cartridge.save_state(state);
+        self.joypad.save_state(state);
+        self.timer.save_state(state);
+        state.write_u8(self.model as u8);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.speed_switch_armed);
+        state.write_bytes(&self.wram);
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.echo);
+        state.write_bytes(&self.oam);
+        state.write_bytes(&self.io_ports);
+        state.write_bytes(&self.hram);
+        state.write_u8(self.ie_register);
+        state.write_u8(self.wram_bank);
+        state.write_u16(self.stack_pointer);
+        state.write_u16(self.program_counter);
+        state.write_u16(self.dma_source);
+        state.write_u16(self.dma_cursor);
+        state.write_bool(self.dma_active);
+        state.write_u8(self.dma_byte);
+        state.write_u8(self.dma_delay);
+        state.write_u16(self.dma_next_source);
+        state.write_bool(self.stat_line);
+        self.serial.save_state(state);
+        state.write_bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(state);
         }
     }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+        self.cartridge.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.model = match state.read_u8()? {
+            0 => Model::Dmg,
+            1 => Model::Cgb,
+            _ => return Err(SnapshotError::InvalidValue),
+        };
+        self.double_speed = state.read_bool()?;
+        self.speed_switch_armed = state.read_bool()?;
+        state.read_into(&mut self.wram)?;
+        state.read_into(&mut self.vram)?;
+        state.read_into(&mut self.echo)?;
+        state.read_into(&mut self.oam)?;
+        state.read_into(&mut self.io_ports)?;
+        state.read_into(&mut self.hram)?;
+        self.ie_register = state.read_u8()?;
+        self.wram_bank = state.read_u8()?;
+        self.stack_pointer = state.read_u16()?;
+        self.program_counter = state.read_u16()?;
+        self.dma_source = state.read_u16()?;
+        self.dma_cursor = state.read_u16()?;
+        self.dma_active = state.read_bool()?;
+        self.dma_byte = state.read_u8()?;
+        self.dma_delay = state.read_u8()?;
+        self.dma_next_source = state.read_u16()?;
+        self.stat_line = state.read_bool()?;
+        self.serial.load_state(state)?;
+        self.sgb = if state.read_bool()? {
+            let mut sgb = Sgb::default();
+            sgb.load_state(state)?;
+            Some(sgb)
+        } else {
+            None
+        };
+        Ok(())
+    }
 }
 
 // General CPU functions
@@ -123,41 +235,34 @@ impl Memory {
         BigEndian::read_u16(&[self.read(c + 1), self.read(c)])
     }
 
-    pub fn load_rom(&mut self, cartridge: Vec<u8>) {
+    pub fn load_rom(&mut self, cartridge: Vec<u8>) -> Result<CartridgeInfo, LoadError> {
+        let info = match menu_header_offset(&cartridge) {
+            Some(menu) => CartridgeInfo::parse_at(&cartridge, menu)?,
+            None => CartridgeInfo::parse(&cartridge)?,
+        };
         if let Some(value) = cartridge.get(0xff70) {
             self.wram_bank = *value;
         }
-        match cartridge[0x147] {
-            0x00 => self.cartridge = Box::new(RomOnly::new(cartridge)),
-            0x01..=0x03 => self.cartridge = Box::new(MBC1::new(cartridge)),
-            0x05 | 0x06 => self.cartridge = Box::new(MBC2::new(cartridge)),
-            0x0f..=0x13 => self.cartridge = Box::new(MBC3::new(cartridge)),
-            bank => {
-                let bank_type = match bank {
-                    0x08 => "08h  ROM+RAM",
-                    0x09 => "09h  ROM+RAM+BATTERY",
-                    0x0b => "0Bh  MMM01",
-                    0x0c => "0Ch  MMM01+RAM",
-                    0x0d => "0Dh  MMM01+RAM+BATTERY",
-                    0x15 => "15h  MBC4",
-                    0x16 => "16h  MBC4+RAM",
-                    0x17 => "17h  MBC4+RAM+BATTERY",
-                    0x19 => "19h  MBC5",
-                    0x1a => "1Ah  MBC5+RAM",
-                    0x1b => "1Bh  MBC5+RAM+BATTERY",
-                    0x1c => "1Ch  MBC5+RUMBLE",
-                    0x1d => "1Dh  MBC5+RUMBLE+RAM",
-                    0x1e => "1Eh  MBC5+RUMBLE+RAM+BATTERY",
-                    0xfc => "FCh  POCKET CAMERA",
-                    0xfd => "FDh  BANDAI TAMA5",
-                    0xfe => "FEh  HuC3",
-                    0xff => "FFh  HuC1+RAM+BATTERY",
-                    _ => "Unknown",
-                };
-                panic!("MBC case not supported {}", bank_type);
-            }
+        self.cartridge = match info.cartridge_type {
+            0x00 => Box::new(RomOnly::new(cartridge)),
+            0x01..=0x03 => Box::new(MBC1::new(cartridge)?),
+            0x05 | 0x06 => Box::new(MBC2::new(cartridge)),
+            0x0b..=0x0d => Box::new(MMM01::new(cartridge)?),
+            0x0f..=0x13 => Box::new(MBC3::new(cartridge)?),
+            0x22 => Box::new(MBC7::new(cartridge)),
+            0xfc => Box::new(PocketCamera::new(cartridge)),
+            0xfd => Box::new(TAMA5::new(cartridge)),
+            0xfe => Box::new(HuC3::new(cartridge)?),
+            0xff => Box::new(HuC1::new(cartridge)?),
+            code => return Err(LoadError::UnsupportedMapper(code)),
+        };
+        self.sgb = if info.sgb && self.model == Model::Dmg {
+            Some(Sgb::default())
+        } else {
+            None
         };
         self.cartridge.debug();
+        Ok(info)
     }
 
     pub fn s_push(&mut self, data: u16) {
@@ -206,49 +311,41 @@ impl Memory {
         self.stack_pointer
     }
 
+    // Completes the running transfer with the byte shifted in from the peer
+    pub fn tick_serial(&mut self) {
+        let divider = self.timer.divider();
+        if let Some(received) = self.serial.tick(divider, self.read_io_ports(0xff01)) {
+            self.write_io_ports(0xff01, received);
+            let sc = self.read_io_ports(0xff02);
+            self.write_io_ports(0xff02, sc & 0b0111_1111);
+            self.set_interrupt_flag(Interrupts::Serial as u8);
+        }
+    }
+
+    pub fn set_interrupt_flag(&mut self, bit: u8) {
+        let interrupt_flags = self.read_io_ports(0xff0f);
+        self.write_io_ports(0xff0f, set_bit_at(interrupt_flags, bit));
+    }
+
     pub fn write_ly(&mut self, data: u8) {
-        self.check_ly_eq_lyc();
         self.io_ports[0x44] = data;
+        self.check_ly_eq_lyc();
     }
 }
 
 // General Timer functions
 impl Memory {
     pub fn get_div(&self) -> u8 {
-        self.read_unchecked(DIVIDER_COUNTER_ADDRESS)
-    }
-    pub fn get_div_counter(&self) -> u16 {
-        (self.get_div() as u16) << 8 | self.read_unchecked(DIVIDER_COUNTER_ADDRESS - 1) as u16
+        self.timer.read(DIVIDER_COUNTER_ADDRESS)
     }
-    pub fn set_div_counter(&mut self, data: u16) {
-        self.write_unchecked(DIVIDER_COUNTER_ADDRESS - 1, data as u8);
-        self.write_unchecked(DIVIDER_COUNTER_ADDRESS, (data >> 8) as u8);
-    }
-
     pub fn get_tima(&self) -> u8 {
-        self.read_unchecked(TIMER_COUNTER_ADDRESS)
-    }
-    pub fn set_tima(&mut self, counter: u8) {
-        self.write_unchecked(TIMER_COUNTER_ADDRESS, counter);
+        self.timer.read(TIMER_COUNTER_ADDRESS)
     }
     pub fn get_tma(&self) -> u8 {
-        self.read_unchecked(TIMER_MODULO_ADDRESS)
+        self.timer.read(TIMER_MODULO_ADDRESS)
     }
     pub fn get_tac(&self) -> u8 {
-        self.read_unchecked(TIMER_CONTROL_ADDRESS)
-    }
-    pub fn tac_enabled(&self) -> u16 {
-        (self.get_tac() >> 2 & 0b1) as u16
-    }
-    pub fn tac_freq(&self) -> u8 {
-        let bits = self.get_tac() & 0b11;
-        match bits {
-            0 => 9, // freq 4096 / 1024
-            1 => 3, // freq 262144 / 16
-            2 => 5, // freq 65536 / 64
-            3 => 7, // freq 16382 / 256
-            _ => panic!("Frequency not supported"),
-        }
+        self.timer.read(TIMER_CONTROL_ADDRESS)
     }
 }
 
@@ -336,18 +433,12 @@ impl Memory {
     }
 
     pub fn lcd_mode(&self) -> LcdMode {
-        let lcd_status = self.read(0xff41);
-        match lcd_status & 0x3 {
-            0x0 => LcdMode::HBlank,
-            0x1 => LcdMode::VBlank,
-            0x2 => LcdMode::ReadOAM,
-            0x3 => LcdMode::ReadVRAM,
-            _ => unreachable!(),
-        }
+        let lcd_status = self.read_io_ports(0xff41);
+        LcdMode::from_bits(lcd_status)
     }
 
     pub fn set_lcd_status(&mut self, status: LcdMode) {
-        let lcd_status = self.read(0xff41);
+        let lcd_status = self.read_io_ports(0xff41);
         let new_status = match status {
             LcdMode::HBlank => {
                 let temp_status = clear_bit_at(lcd_status, 1);
@@ -366,17 +457,34 @@ impl Memory {
                 set_bit_at(temp_status, 0)
             }
         };
-        self.write_unchecked(0xff41, new_status);
+        self.write_io_ports(0xff41, new_status);
+        self.update_stat_line();
+    }
+
+    // ORs every enabled STAT source, mode 2 is also checked at the start of line 144
+    pub fn update_stat_line(&mut self) {
+        let stat = self.read_io_ports(0xff41);
+        let mode = LcdMode::from_bits(stat);
+        let oam = mode == LcdMode::ReadOAM || (mode == LcdMode::VBlank && self.get_ly() == 144);
+        let line = self.is_lcd_enabled()
+            && ((get_bit_at(stat, 3) && mode == LcdMode::HBlank)
+                || (get_bit_at(stat, 4) && mode == LcdMode::VBlank)
+                || (get_bit_at(stat, 5) && oam)
+                || (get_bit_at(stat, 6) && get_bit_at(stat, 2)));
+        if line && !self.stat_line {
+            self.set_interrupt_flag(Interrupts::LCDStat as u8);
+        }
+        self.stat_line = line;
     }
 
     pub fn set_coincidence_flag(&mut self) {
-        let lcd_status = self.read(0xff41);
-        self.write_unchecked(0xff41, set_bit_at(lcd_status, 2));
+        let lcd_status = self.read_io_ports(0xff41);
+        self.write_io_ports(0xff41, set_bit_at(lcd_status, 2));
     }
 
     pub fn clear_coincidence_flag(&mut self) {
-        let lcd_status = self.read(0xff41);
-        self.write_unchecked(0xff41, clear_bit_at(lcd_status, 2));
+        let lcd_status = self.read_io_ports(0xff41);
+        self.write_io_ports(0xff41, clear_bit_at(lcd_status, 2));
     }
 
     pub fn increment_ly(&mut self) -> u8 {
@@ -392,6 +500,7 @@ impl Memory {
         } else {
             self.clear_coincidence_flag();
         }
+        self.update_stat_line();
     }
 
     pub fn get_ly(&self) -> u8 {
@@ -403,23 +512,44 @@ impl Memory {
     }
 
     fn start_dma_transfer(&mut self, data: u8) {
-        self.dma_cursor = 0;
-        self.dma_copy_address = (data as u16) << 8;
-        self.dma_copy_in_progress = true;
+        self.dma_next_source = (data as u16) << 8;
+        self.dma_delay = 2;
+    }
+
+    pub fn is_dma_active(&self) -> bool {
+        self.dma_active
+    }
+
+    // The CPU fights with the DMA for the bus the source is on, OAM is unreachable
+    // and HRAM and the IO registers are always free
+    fn dma_conflict(&self, address: u16) -> bool {
+        let vram_bus = |address| (0x8000..=0x9fff).contains(&address);
+        self.dma_active && address < 0xfe00 && vram_bus(address) == vram_bus(self.dma_source)
     }
 
+    // Called once per M-cycle
     pub fn dma_copy_byte(&mut self) {
-        if !self.dma_copy_in_progress {
+        if self.dma_delay > 0 {
+            self.dma_delay -= 1;
+            if self.dma_delay == 0 {
+                self.dma_source = self.dma_next_source;
+                self.dma_cursor = 0;
+                self.dma_active = true;
+            }
+        }
+        if !self.dma_active {
             return;
         }
-        if self.dma_cursor > 0xA0 {
-            self.dma_copy_in_progress = false;
-            self.dma_cursor = 0;
-            self.dma_copy_address = 0;
-        } else {
-            let data = self.read_unchecked(self.dma_copy_address + self.dma_cursor);
-            self.write_unchecked(0xfe00 + self.dma_cursor, data);
-            self.dma_cursor += 1;
+        // Sources from 0xe000 up read WRAM through the echo region
+        let mut source = self.dma_source + self.dma_cursor;
+        if source >= 0xe000 {
+            source -= 0x2000;
+        }
+        self.dma_byte = self.read_unchecked(source);
+        self.oam[self.dma_cursor as usize] = self.dma_byte;
+        self.dma_cursor += 1;
+        if self.dma_cursor == 0xa0 {
+            self.dma_active = false;
         }
     }
 }
@@ -486,13 +616,24 @@ impl Memory {
         self.hram[(address - 0xff80) as usize] = data;
     }
 
+    // Raw contents of an IO register before the io_register read mask is applied
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            0xff00 => self.joypad.read(),
+            0xff04..=0xff07 => self.timer.read(address),
+            // Bits 0-2 return '0' when the LCD is off.
+            0xff41 if !self.is_lcd_enabled() => self.read_io_ports(address) & 0b1111_1000,
+            0xff4d => (self.double_speed as u8) << 7 | self.speed_switch_armed as u8,
+            _ => self.read_io_ports(address),
+        }
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         match address {
-            0x8000..=0x9fff if self.dma_copy_in_progress => 0xff,
+            0xfe00..=0xfeff if self.dma_active => 0xff,
+            _ if self.dma_conflict(address) => self.dma_byte,
             0x8000..=0x9fff if self.lcd_mode() == LcdMode::ReadVRAM => 0xff,
-            0xfe00..=0xfe9f if self.dma_copy_in_progress => 0xff,
             0xa000..=0xbfff if !self.cartridge.ram_enabled() => 0xff,
-            0xfe00..=0xfe9f if self.dma_copy_in_progress => 0xff,
             0xfe00..=0xfe9f if self.lcd_mode() == LcdMode::ReadOAM => 0xff,
             0xfe00..=0xfe9f if self.lcd_mode() == LcdMode::ReadVRAM => 0xff,
             _ => self.read_unchecked(address),
@@ -507,51 +648,33 @@ impl Memory {
             0xe000..=0xfdff => self.read_echo(address),
             0xfe00..=0xfe9f => self.read_oam(address),
             0xfea0..=0xfeff => 0,
-            0xff00 => !self.read_io_ports(address),
-            0xff01..=0xff0e => self.read_io_ports(address),
-            0xff0f => self.read_io_ports(address) | 0b1110_0000,
-            0xff10..=0xff40 => self.read_io_ports(address),
-            0xff41 => {
-                let stat = self.read_io_ports(address) | 0b1000_0000;
-                if !self.is_lcd_enabled() {
-                    return stat & 0b1111_1000; // Bits 0-2 return '0' when the LCD is off.
-                }
-                stat
-            }
-            0xff42..=0xff7f => self.read_io_ports(address),
+            0xff00..=0xff7f => io_register(address).read(self.model, self.read_io(address)),
             0xff80..=0xfffe => self.read_hram(address),
             0xffff => self.ie_register,
         }
     }
     pub fn write(&mut self, address: u16, data: u8) {
         match address {
-            0x8000..=0x9fff if self.dma_copy_in_progress => {}
+            0xfe00..=0xfeff if self.dma_active => {}
+            _ if self.dma_conflict(address) => {}
             0x8000..=0x9fff if self.lcd_mode() == LcdMode::ReadVRAM => {}
-            0xfe00..=0xfe9f if self.dma_copy_in_progress => {}
             0xa000..=0xbfff if !self.cartridge.ram_enabled() => {}
-            0xfe00..=0xfe9f if self.dma_copy_in_progress => {}
             0xfe00..=0xfe9f if self.lcd_mode() == LcdMode::ReadOAM => {}
             0xfe00..=0xfe9f if self.lcd_mode() == LcdMode::ReadVRAM => {}
-            // Writes to DIV resets DIV and counter
-            0xff04 => {
-                self.write_io_ports(0xff03, 0);
-                self.write_io_ports(0xff04, 0);
-            }
-            // Write to TIMA ignored
-            0xff05 if self.tima_reloading => {}
-            // Write to TMA also loads TIMA
-            0xff06 if self.tima_reloading => {
-                self.write_unchecked(0xff05, data);
-                self.write_unchecked(0xff06, data);
-            }
             0xff41 => {
-                let stat_register = self.read_unchecked(address) & 0b1000_0111;
-                let new_stat_register = data & 0b0111_1000;
-                self.write_io_ports(address, stat_register | new_stat_register)
+                let stat = io_register(address);
+                let old = self.read_io(address);
+                // DMG briefly enables the HBlank, VBlank and LYC sources on every write
+                if self.model == Model::Dmg {
+                    self.write_io_ports(address, stat.write(self.model, old, 0b0101_1000));
+                    self.update_stat_line();
+                }
+                self.write_io_ports(address, stat.write(self.model, old, data));
+                self.update_stat_line();
             }
             0xff44 => {
-                self.check_ly_eq_lyc();
                 self.write_io_ports(address, 0);
+                self.check_ly_eq_lyc();
             }
             _ => self.write_unchecked(address, data),
         }
@@ -565,24 +688,42 @@ impl Memory {
             0xe000..=0xfdff => self.write_echo(address, data),
             0xfe00..=0xfe9f => self.write_oam(address, data),
             0xfea0..=0xfeff => {}
-            0xff01 => {
-                self.write_io_ports(address, data);
-                self.write_io_ports(0xff02, 0x81);
-                let c = self.read_io_ports(0xff01) as char;
-                let mut out = std::io::stdout();
-                print!("{}", c);
-                let _ = out.flush();
+            0xff00 => {
+                let data = io_register(address).write(self.model, self.read_io(address), data);
+                if self.joypad.write(data) {
+                    self.set_interrupt_flag(Interrupts::Joypad as u8);
+                }
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_joypad(data, &self.vram, self.io_ports[0x40]);
+                    self.joypad.set_players(sgb.players);
+                }
+            }
+            0xff04..=0xff07 => {
+                let data = io_register(address).write(self.model, self.read_io(address), data);
+                self.timer.write(address, data);
+            }
+            0xff4d => {
+                let key1 = io_register(address).write(self.model, self.read_io(address), data);
+                self.speed_switch_armed = get_bit_at(key1, 0);
+            }
+            0xff02 => {
+                let sc = io_register(address).write(self.model, self.read_io_ports(address), data);
+                self.write_io_ports(address, sc);
+                // Only the internal clock runs, no peer drives an external one
+                if sc & 0x81 == 0x81 {
+                    self.serial.start(get_bit_at(sc, 1));
+                }
             }
             0xff40 => {
                 let enabling_lcd = get_bit_at(data, 7);
+                self.write_io_ports(address, data);
                 if enabling_lcd {
                     self.check_ly_eq_lyc();
                 }
-                self.write_io_ports(address, data);
             }
             0xff45 => {
-                self.check_ly_eq_lyc();
                 self.write_io_ports(address, data);
+                self.check_ly_eq_lyc();
             }
             0xff46 => {
                 self.start_dma_transfer(data);
@@ -590,7 +731,11 @@ impl Memory {
             }
             0xff80..=0xfffe => self.write_hram(address, data),
             0xffff => self.ie_register = data,
-            _ => self.write_io_ports(address, data),
+            _ => {
+                let old = self.read_io_ports(address);
+                let new = io_register(address).write(self.model, old, data);
+                self.write_io_ports(address, new);
+            }
         }
     }
 }
@@ -652,8 +797,8 @@ impl fmt::Debug for Memory {
                 0xff05 (TIMA) Timer counter: {:02X}\n\
                 0xff06 (TMA) Timer modulo: {:02X}\n\
                 0xff07 (TAC) Timer control: {:02X}\n",
-                self.get_tac(),
-                self.tac_enabled(),
+                self.timer.selected_bit(),
+                self.timer.is_enabled(),
                 self.get_div(),
                 self.get_tima(),
                 self.get_tma(),
@@ -0,0 +1,263 @@
+// Input movie format, all values little endian:
+//
+//   "SGBM" | version: u8 | rom crc32: u32 | start time: i64 (unix seconds)
+//   end frame: u64
+//   start: u8 (0 = power-on, 1 = snapshot) | [snapshot length: u32 | snapshot]
+//   input count: u32 | count x (frame: u64 | buttons: u8 for each of the 4 joypads)
+//
+// Every entry is a joypad state change applied before emulating `frame`, playback
+// runs until the emulator reaches the end frame.
+use super::clock::FixedClock;
+use super::emulator::Emulator;
+use super::joypad::MAX_PLAYERS;
+use super::snapshot::SnapshotError;
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+use std::path::Path;
+
+const MOVIE_MAGIC: &[u8; 4] = b"SGBM";
+const MOVIE_VERSION: u8 = 3;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    InvalidStart(u8),
+    RomMismatch { expected: u32, found: u32 },
+    Snapshot(SnapshotError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(f, "{}", e),
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(v) => write!(f, "unsupported movie version {}", v),
+            MovieError::Truncated => write!(f, "movie file is truncated"),
+            MovieError::InvalidStart(s) => write!(f, "invalid movie start state {}", s),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded with ROM {:08X}, loaded ROM is {:08X}",
+                expected, found
+            ),
+            MovieError::Snapshot(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<std::io::Error> for MovieError {
+    fn from(e: std::io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+impl From<SnapshotError> for MovieError {
+    fn from(e: SnapshotError) -> Self {
+        MovieError::Snapshot(e)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum StartState {
+    PowerOn,
+    Snapshot(Vec<u8>),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Movie {
+    pub rom_checksum: u32,
+    pub start_time: i64,
+    pub end_frame: u64,
+    pub start: StartState,
+    pub inputs: Vec<(u64, [u8; MAX_PLAYERS])>,
+}
+
+impl Movie {
+    /// Starts a recording from the emulator's current state. `start_time` is the
+    /// wall clock reported to the cartridge for the whole movie.
+    pub fn record(ctx: &mut Emulator, start: StartState, start_time: i64) -> Self {
+        let movie = Self {
+            rom_checksum: ctx.rom_checksum,
+            start_time,
+            end_frame: ctx.frame_count,
+            start,
+            inputs: vec![(ctx.frame_count, ctx.memory.joypad.all_buttons())],
+        };
+        ctx.memory
+            .cartridge
+            .set_clock(Box::new(FixedClock(movie.start_time)));
+        movie
+    }
+
+    pub fn record_input(&mut self, frame: u64, buttons: [u8; MAX_PLAYERS]) {
+        let last = self.inputs.last().map(|(_, buttons)| *buttons);
+        if Some(buttons) != last {
+            self.inputs.push((frame, buttons));
+        }
+    }
+
+    /// Marks the frame the recording stopped at, call it before saving.
+    pub fn finish(&mut self, frame: u64) {
+        self.end_frame = frame;
+    }
+
+    /// Puts a freshly loaded emulator in the movie's starting state.
+    pub fn begin(&self, ctx: &mut Emulator) -> Result<(), MovieError> {
+        if ctx.rom_checksum != self.rom_checksum {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_checksum,
+                found: ctx.rom_checksum,
+            });
+        }
+        if let StartState::Snapshot(snapshot) = &self.start {
+            ctx.load_state(snapshot)?;
+        }
+        ctx.memory
+            .cartridge
+            .set_clock(Box::new(FixedClock(self.start_time)));
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MOVIE_MAGIC.to_vec();
+        data.push(MOVIE_VERSION);
+        data.extend_from_slice(&self.rom_checksum.to_le_bytes());
+        data.extend_from_slice(&self.start_time.to_le_bytes());
+        data.extend_from_slice(&self.end_frame.to_le_bytes());
+        match &self.start {
+            StartState::PowerOn => data.push(0),
+            StartState::Snapshot(snapshot) => {
+                data.push(1);
+                data.extend_from_slice(&(snapshot.len() as u32).to_le_bytes());
+                data.extend_from_slice(snapshot);
+            }
+        }
+        data.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for (frame, buttons) in &self.inputs {
+            data.extend_from_slice(&frame.to_le_bytes());
+            data.extend_from_slice(buttons);
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let take = |cursor: &mut usize, len: usize| -> Result<&[u8], MovieError> {
+            let bytes = data.get(*cursor..*cursor + len).ok_or(MovieError::Truncated)?;
+            *cursor += len;
+            Ok(bytes)
+        };
+        let mut cursor = 0;
+        if take(&mut cursor, 4)? != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = take(&mut cursor, 1)?[0];
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_checksum = LittleEndian::read_u32(take(&mut cursor, 4)?);
+        let start_time = LittleEndian::read_i64(take(&mut cursor, 8)?);
+        let end_frame = LittleEndian::read_u64(take(&mut cursor, 8)?);
+        let start = match take(&mut cursor, 1)?[0] {
+            0 => StartState::PowerOn,
+            1 => {
+                let len = LittleEndian::read_u32(take(&mut cursor, 4)?) as usize;
+                StartState::Snapshot(take(&mut cursor, len)?.to_vec())
+            }
+            start => return Err(MovieError::InvalidStart(start)),
+        };
+        let count = LittleEndian::read_u32(take(&mut cursor, 4)?);
+        let mut inputs = Vec::new();
+        for _ in 0..count {
+            let frame = LittleEndian::read_u64(take(&mut cursor, 8)?);
+            let mut buttons = [0; MAX_PLAYERS];
+            buttons.copy_from_slice(take(&mut cursor, MAX_PLAYERS)?);
+            inputs.push((frame, buttons));
+        }
+        Ok(Self {
+            rom_checksum,
+            start_time,
+            end_frame,
+            start,
+            inputs,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Feeds a movie's inputs to the emulator, frame by frame.
+pub struct Playback {
+    movie: Movie,
+    cursor: usize,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, cursor: 0 }
+    }
+
+    pub fn begin(&mut self, ctx: &mut Emulator) -> Result<(), MovieError> {
+        self.cursor = 0;
+        self.movie.begin(ctx)
+    }
+
+    // Call before emulating each frame
+    pub fn apply(&mut self, ctx: &mut Emulator) {
+        while let Some((frame, buttons)) = self.movie.inputs.get(self.cursor) {
+            if *frame > ctx.frame_count {
+                break;
+            }
+            for (player, buttons) in buttons.iter().enumerate() {
+                ctx.set_player_buttons(player, *buttons);
+            }
+            self.cursor += 1;
+        }
+    }
+
+    pub fn is_finished(&self, ctx: &Emulator) -> bool {
+        self.cursor >= self.movie.inputs.len() && ctx.frame_count >= self.movie.end_frame
+    }
+}
+
+// CRC-32 (IEEE) of the whole ROM, identifies the game a movie belongs to
+pub fn rom_checksum(rom: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in rom {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_rom_checksum() {
+    assert_eq!(rom_checksum(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn test_movie_round_trip() {
+    let movie = Movie {
+        rom_checksum: 0x1234_5678,
+        start_time: 1_600_000_000,
+        end_frame: 60,
+        start: StartState::Snapshot(vec![1, 2, 3]),
+        inputs: vec![(10, [0b0001_0000, 0, 0, 0]), (42, [0, 0b1000_0000, 0, 0])],
+    };
+    let data = movie.to_bytes();
+    assert_eq!(Movie::from_bytes(&data).unwrap(), movie);
+    assert!(Movie::from_bytes(&data[..data.len() - 1]).is_err());
+}
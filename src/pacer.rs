@@ -0,0 +1,192 @@
+use super::constants::*;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Pace {
+    Normal,
+    FastForward,
+    SlowMotion,
+    Uncapped,
+}
+
+/// Ties emulation to the DMG clock: one frame every 70224 dots at 4194304 Hz (~59.73 Hz).
+pub struct FramePacer {
+    pace: Pace,
+    fast_forward_multiplier: f64,
+    slow_motion_multiplier: f64,
+    max_frame_skip: u32,
+    frames_skipped: u32,
+    next_frame: Instant,
+    last_present: Instant,
+}
+
+impl FramePacer {
+    pub fn default() -> Self {
+        let now = Instant::now();
+        let mut pacer = Self {
+            pace: Pace::Normal,
+            fast_forward_multiplier: 4.0,
+            slow_motion_multiplier: 0.5,
+            max_frame_skip: 4,
+            frames_skipped: 0,
+            next_frame: now,
+            last_present: now,
+        };
+        pacer.resync();
+        pacer
+    }
+
+    pub fn pace(&self) -> Pace {
+        self.pace
+    }
+
+    pub fn set_pace(&mut self, pace: Pace) {
+        self.pace = pace;
+        self.resync();
+    }
+
+    fn toggle(&mut self, pace: Pace) {
+        if self.pace == pace {
+            self.set_pace(Pace::Normal);
+        } else {
+            self.set_pace(pace);
+        }
+    }
+
+    pub fn toggle_fast_forward(&mut self) {
+        self.toggle(Pace::FastForward);
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.toggle(Pace::SlowMotion);
+    }
+
+    pub fn toggle_uncapped(&mut self) {
+        self.toggle(Pace::Uncapped);
+    }
+
+    pub fn set_fast_forward_multiplier(&mut self, multiplier: f64) {
+        if multiplier > 0.0 {
+            self.fast_forward_multiplier = multiplier;
+            self.resync();
+        }
+    }
+
+    pub fn set_slow_motion_multiplier(&mut self, multiplier: f64) {
+        if multiplier > 0.0 {
+            self.slow_motion_multiplier = multiplier;
+            self.resync();
+        }
+    }
+
+    pub fn set_max_frame_skip(&mut self, frames: u32) {
+        self.max_frame_skip = frames;
+    }
+
+    /// Emulation speed relative to real hardware, `None` when uncapped.
+    pub fn speed(&self) -> Option<f64> {
+        match self.pace {
+            Pace::Normal => Some(1.0),
+            Pace::FastForward => Some(self.fast_forward_multiplier),
+            Pace::SlowMotion => Some(self.slow_motion_multiplier),
+            Pace::Uncapped => None,
+        }
+    }
+
+    /// Host time one emulated frame should take at the current speed.
+    pub fn frame_duration(&self) -> Option<Duration> {
+        self.speed()
+            .map(|speed| Duration::from_secs_f64(real_frame_duration().as_secs_f64() / speed))
+    }
+
+    fn resync(&mut self) {
+        self.frames_skipped = 0;
+        self.next_frame = Instant::now() + self.frame_duration().unwrap_or_default();
+    }
+
+    /// Whether the frame that was just emulated should be shown. Frames are
+    /// skipped while the host is behind, up to `max_frame_skip` in a row.
+    pub fn should_present(&mut self) -> bool {
+        let now = Instant::now();
+        let on_time = match self.pace {
+            // Never present faster than the real display refreshes
+            Pace::Uncapped => now.duration_since(self.last_present) >= real_frame_duration(),
+            _ => now <= self.next_frame,
+        };
+        if on_time || self.frames_skipped >= self.max_frame_skip {
+            self.frames_skipped = 0;
+            self.last_present = now;
+            true
+        } else {
+            self.frames_skipped += 1;
+            false
+        }
+    }
+
+    /// Sleeps until the current frame's deadline and schedules the next one.
+    pub fn wait(&mut self) {
+        let frame_duration = match self.frame_duration() {
+            Some(duration) => duration,
+            None => return,
+        };
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > frame_duration * (self.max_frame_skip + 1) {
+            // Too far behind to catch up by skipping, drop the lost time
+            self.next_frame = now;
+        }
+        self.next_frame += frame_duration;
+    }
+}
+
+pub fn real_frame_duration() -> Duration {
+    Duration::from_secs_f64(DOTS_PER_FRAME as f64 / CPU_CLOCK_HZ as f64)
+}
+
+impl fmt::Debug for FramePacer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PACER ------------------------\n\
+            Pace: {:?}\n\
+            Speed: {:?}\n\
+            Frames skipped: {}\n",
+            self.pace,
+            self.speed(),
+            self.frames_skipped
+        )
+    }
+}
+
+#[test]
+fn test_frame_duration() {
+    let mut pacer = FramePacer::default();
+    let real = real_frame_duration().as_secs_f64();
+    assert!((1.0 / real - 59.7275).abs() < 0.001);
+    assert_eq!(pacer.frame_duration(), Some(real_frame_duration()));
+    pacer.set_fast_forward_multiplier(2.0);
+    pacer.toggle_fast_forward();
+    assert_eq!(pacer.pace(), Pace::FastForward);
+    assert!((pacer.frame_duration().unwrap().as_secs_f64() - real / 2.0).abs() < 1e-9);
+    pacer.toggle_slow_motion();
+    assert!((pacer.frame_duration().unwrap().as_secs_f64() - real * 2.0).abs() < 1e-9);
+    pacer.toggle_uncapped();
+    assert_eq!(pacer.frame_duration(), None);
+    pacer.toggle_uncapped();
+    assert_eq!(pacer.pace(), Pace::Normal);
+}
+
+#[test]
+fn test_frame_skip() {
+    let mut pacer = FramePacer::default();
+    pacer.set_max_frame_skip(2);
+    assert!(pacer.should_present());
+    // Simulate a host that fell behind by several frames
+    pacer.next_frame = Instant::now() - real_frame_duration() * 2;
+    assert!(!pacer.should_present());
+    assert!(!pacer.should_present());
+    assert!(pacer.should_present());
+}
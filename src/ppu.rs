@@ -32,15 +32,18 @@ impl RenderProps {
   }
 }
 
-fn get_color(pixel: u8, palette: u8) -> u32 {
-  let color = match pixel {
+fn get_shade(pixel: u8, palette: u8) -> u8 {
+  match pixel {
     0x00 => palette & 0b0000_0011,
     0x01 => (palette & 0b0000_1100) >> 2,
     0x02 => (palette & 0b0011_0000) >> 4,
     0x03 => (palette & 0b1100_0000) >> 6,
     _ => unreachable!(),
-  };
-  match color {
+  }
+}
+
+fn get_color(shade: u8) -> u32 {
+  match shade {
     0x00 => 0xff_ff_ff,
     0x01 => 0xea_ec_ee,
     0x02 => 0x56_65_73,
@@ -195,10 +198,18 @@ pub fn draw_scan_line(ctx: &mut Emulator) {
     render_sprites(ctx, &mut buffer);
   }
 
+  let shades: Vec<u8> = buffer
+    .into_iter()
+    .map(|(pixel, palette)| get_shade(pixel, palette))
+    .collect();
+  // The SGB recolors the shades on its own
+  if let Some(sgb) = &mut ctx.memory.sgb {
+    sgb.draw_line(render_props.ly, &shades);
+  }
   let current_line = render_props.ly as usize * SCREEN_WIDTH;
-  buffer
+  shades
     .into_iter()
-    .map(|(pixel, palette)| get_color(pixel, palette))
+    .map(get_color)
     .enumerate()
-    .for_each(|(n, pixel)| ctx.frame_buffer[current_line + n] = pixel)
+    .for_each(|(n, pixel)| ctx.back_buffer[current_line + n] = pixel)
 }
@@ -0,0 +1,253 @@
+use super::constants::SCREEN_WIDTH;
+use super::screenshot::save_png;
+use super::serial::SerialDevice;
+use std::path::PathBuf;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+
+// Status bits sent back at the end of every packet
+const CHECKSUM_ERROR: u8 = 0b0000_0001;
+const PRINTING: u8 = 0b0000_0010;
+const IMAGE_FULL: u8 = 0b0000_0100;
+const UNPROCESSED: u8 = 0b0000_1000;
+
+// Room for 9 DATA packets of two tile rows each
+const BUFFER_SIZE: usize = 0x1680;
+const TILE_ROW_SIZE: usize = SCREEN_WIDTH / 8 * 16;
+// Packets answered as busy after a PRINT, games poll STATUS until it's done
+const PRINTING_PACKETS: u8 = 4;
+const DEFAULT_EXPOSURE: u8 = 0x40;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// Runs of the following byte have bit 7 set, literal bytes have it clear
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            if let Some(byte) = bytes.next() {
+                output.resize(output.len() + (control & 0x7f) as usize + 2, *byte);
+            }
+        } else {
+            output.extend(bytes.by_ref().take(*control as usize + 1));
+        }
+    }
+    output
+}
+
+/// Renders tile data of 20 tiles per row, returning the shade of every pixel.
+pub fn tile_rows_to_shades(data: &[u8]) -> Vec<u8> {
+    let rows = data.len() / TILE_ROW_SIZE;
+    let mut shades = vec![0; SCREEN_WIDTH * rows * 8];
+    for (tile, bytes) in data[..rows * TILE_ROW_SIZE].chunks_exact(16).enumerate() {
+        let (tile_x, tile_y) = (tile % (SCREEN_WIDTH / 8), tile / (SCREEN_WIDTH / 8));
+        for (row, planes) in bytes.chunks_exact(2).enumerate() {
+            for column in 0..8 {
+                let bit = 7 - column;
+                let shade = (planes[0] >> bit) & 0b1 | ((planes[1] >> bit) & 0b1) << 1;
+                let (x, y) = (tile_x * 8 + column, tile_y * 8 + row);
+                shades[y * SCREEN_WIDTH + x] = shade;
+            }
+        }
+    }
+    shades
+}
+
+/// Game Boy Printer, prints are saved as numbered PNG files in the output directory.
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    sum: u16,
+    status: u8,
+    printing_packets: u8,
+    buffer: Vec<u8>,
+    output_dir: PathBuf,
+    prints: u32,
+}
+
+impl Printer {
+    pub fn new<P: Into<PathBuf>>(output_dir: P) -> Self {
+        Self {
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            sum: 0,
+            status: 0,
+            printing_packets: 0,
+            buffer: Vec::new(),
+            output_dir: output_dir.into(),
+            prints: 0,
+        }
+    }
+
+    pub fn print_path(&self, print: u32) -> PathBuf {
+        self.output_dir.join(format!("print_{:03}.png", print))
+    }
+
+    fn add_to_sum(&mut self, data: u8) {
+        self.sum = self.sum.wrapping_add(data as u16);
+    }
+
+    fn process(&mut self) {
+        self.printing_packets = self.printing_packets.saturating_sub(1);
+        if self.printing_packets == 0 {
+            self.status &= !PRINTING;
+        }
+        if self.sum != self.checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.printing_packets = 0;
+            }
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    std::mem::take(&mut self.data)
+                };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                if !self.buffer.is_empty() {
+                    self.status |= UNPROCESSED;
+                }
+                if self.buffer.len() >= BUFFER_SIZE {
+                    self.status |= IMAGE_FULL;
+                }
+            }
+            PRINT if self.data.len() >= 4 => {
+                // A print of zero sheets only feeds paper
+                if self.data[0] > 0 {
+                    self.print(self.data[2], self.data[3] & 0x7f);
+                }
+                self.buffer.clear();
+                self.status = (self.status & !(UNPROCESSED | IMAGE_FULL)) | PRINTING;
+                self.printing_packets = PRINTING_PACKETS;
+            }
+            // STATUS and unknown commands only report the status
+            _ => {}
+        }
+    }
+
+    // The palette maps colors to shades like BGP, exposure darkens by up to 25% either way
+    fn print(&mut self, palette: u8, exposure: u8) {
+        let shades = tile_rows_to_shades(&self.buffer);
+        if shades.is_empty() {
+            return;
+        }
+        let palette = if palette == 0 { 0xe4 } else { palette };
+        let darkness = 1.0 + (exposure as f32 - DEFAULT_EXPOSURE as f32) / 64.0 * 0.25;
+        let pixels = shades
+            .iter()
+            .map(|color| {
+                let shade = (palette >> (color * 2)) & 0b11;
+                let luminance = 255.0 - (shade as f32 * 85.0 * darkness).clamp(0.0, 255.0);
+                let luminance = luminance as u32;
+                luminance << 16 | luminance << 8 | luminance
+            })
+            .collect::<Vec<u32>>();
+        // Prints from earlier sessions in the same directory are kept
+        while self.print_path(self.prints).exists() {
+            self.prints += 1;
+        }
+        let path = self.print_path(self.prints);
+        self.prints += 1;
+        let height = pixels.len() / SCREEN_WIDTH;
+        match save_png(&path, &pixels, SCREEN_WIDTH, height) {
+            Ok(_) => println!("Printed to {}", path.display()),
+            Err(e) => println!("Unable to save print: {}", e),
+        }
+    }
+}
+
+// Packets are 0x88 0x33, command, compression, length, data and checksum,
+// followed by two bytes where the printer answers 0x81 and its status
+impl SerialDevice for Printer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        let response = match self.state {
+            State::Alive => 0x81,
+            State::Status => self.status,
+            _ => 0x00,
+        };
+        self.state = match self.state {
+            State::Magic1 if data == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if data == 0x33 => State::Command,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = data;
+                self.sum = 0;
+                self.data.clear();
+                self.add_to_sum(data);
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = data & 0b1 == 1;
+                self.add_to_sum(data);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = data as u16;
+                self.add_to_sum(data);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (data as u16) << 8;
+                self.add_to_sum(data);
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.data.push(data);
+                self.add_to_sum(data);
+                if self.data.len() >= self.length as usize {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.checksum = data as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.checksum |= (data as u16) << 8;
+                self.process();
+                State::Alive
+            }
+            State::Alive => State::Status,
+            State::Status => State::Magic1,
+        };
+        response
+    }
+}
@@ -1,4 +1,5 @@
 use super::constants::*;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
 use super::utils::get_bit_at;
 use byteorder::{BigEndian, ByteOrder};
 use std::fmt;
@@ -35,6 +36,24 @@ impl Registers {
     }
   }
 
+  pub fn save_state(&self, state: &mut StateWriter) {
+    for register in &[self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l] {
+      state.write_u8(*register);
+    }
+  }
+
+  pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+    self.a = state.read_u8()?;
+    self.set_f(state.read_u8()?);
+    self.b = state.read_u8()?;
+    self.c = state.read_u8()?;
+    self.d = state.read_u8()?;
+    self.e = state.read_u8()?;
+    self.h = state.read_u8()?;
+    self.l = state.read_u8()?;
+    Ok(())
+  }
+
   pub fn get_flag(&self, flag: Flags) -> u8 {
     match flag {
       Flags::Z => get_bit_at(self.get_f(), 7) as u8,
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    Io(std::io::Error),
+    Png(png::EncodingError),
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::Io(e) => write!(f, "{}", e),
+            ScreenshotError::Png(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+impl From<std::io::Error> for ScreenshotError {
+    fn from(e: std::io::Error) -> Self {
+        ScreenshotError::Io(e)
+    }
+}
+
+impl From<png::EncodingError> for ScreenshotError {
+    fn from(e: png::EncodingError) -> Self {
+        ScreenshotError::Png(e)
+    }
+}
+
+// Pixels are 0x00RRGGBB, as minifb expects them
+pub fn to_rgb(pixels: &[u32]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            vec![r, g, b]
+        })
+        .collect()
+}
+
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+) -> Result<(), ScreenshotError> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&to_rgb(pixels))?;
+    Ok(())
+}
+
+/// Stable 64-bit FNV-1a hash of the RGB values, for golden-image tests.
+pub fn frame_hash(pixels: &[u32]) -> u64 {
+    to_rgb(pixels)
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[test]
+fn test_frame_hash() {
+    assert_eq!(frame_hash(&[]), 0xcbf2_9ce4_8422_2325);
+    // Unused top byte is ignored
+    assert_eq!(frame_hash(&[0x00_ff_ff_ff]), frame_hash(&[0xff_ff_ff_ff]));
+    assert_ne!(frame_hash(&[0x00_ff_ff_ff, 0]), frame_hash(&[0, 0x00_ff_ff_ff]));
+}
@@ -0,0 +1,92 @@
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
+use std::io::Write;
+
+/// Peer at the other end of the link cable, both sides shift one byte out and one in per transfer.
+pub trait SerialDevice {
+    fn exchange(&mut self, data: u8) -> u8;
+}
+
+/// Prints every byte sent, the way test ROMs report their results.
+/// Nothing drives the lines back, so the game reads 0xff.
+pub struct Console;
+
+impl SerialDevice for Console {
+    fn exchange(&mut self, data: u8) -> u8 {
+        print!("{}", data as char);
+        let _ = std::io::stdout().flush();
+        0xff
+    }
+}
+
+/// Shifts one bit per falling edge of a divider bit, bit 8 (8192 Hz) or bit 3
+/// (262144 Hz) with the CGB fast clock, so a DIV write can shift a bit early.
+pub struct Serial {
+    device: Box<dyn SerialDevice>,
+    // Bits left to shift in the running transfer
+    bits_left: u8,
+    fast_clock: bool,
+    prev_clock: bool,
+}
+
+impl Serial {
+    pub fn default() -> Self {
+        Self {
+            device: Box::new(Console),
+            bits_left: 0,
+            fast_clock: false,
+            prev_clock: false,
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn start(&mut self, fast_clock: bool) {
+        self.bits_left = 8;
+        self.fast_clock = fast_clock;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bits_left > 0
+    }
+
+    fn clock_bit(&self) -> u8 {
+        if self.fast_clock {
+            3
+        } else {
+            8
+        }
+    }
+
+    // Returns the received byte once the last bit of `data` is shifted out
+    pub fn tick(&mut self, divider: u16, data: u8) -> Option<u8> {
+        let clock = (divider >> self.clock_bit()) & 1 == 1;
+        let falling_edge = self.prev_clock && !clock;
+        self.prev_clock = clock;
+        if !falling_edge || self.bits_left == 0 {
+            return None;
+        }
+        self.bits_left -= 1;
+        if self.bits_left > 0 {
+            return None;
+        }
+        Some(self.device.exchange(data))
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.bits_left);
+        state.write_bool(self.fast_clock);
+        state.write_bool(self.prev_clock);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+        self.bits_left = state.read_u8()?;
+        if self.bits_left > 8 {
+            return Err(SnapshotError::InvalidValue);
+        }
+        self.fast_clock = state.read_bool()?;
+        self.prev_clock = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+use super::constants::*;
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
+use std::cmp::Ordering;
+
+// Position of the Game Boy screen inside the border
+const SCREEN_X: usize = (SGB_SCREEN_WIDTH - SCREEN_WIDTH) / 2;
+const SCREEN_Y: usize = (SGB_SCREEN_HEIGHT - SCREEN_HEIGHT) / 2;
+// Palettes are assigned per 8x8 cell of the screen
+const ATTRIBUTE_WIDTH: usize = SCREEN_WIDTH / 8;
+const ATTRIBUTE_HEIGHT: usize = SCREEN_HEIGHT / 8;
+const TRANSFER_SIZE: usize = 0x1000;
+const SYSTEM_PALETTES: usize = 512;
+// Colors the SGB starts with, from white to black
+const DEFAULT_PALETTE: [u16; 4] = [0x67bf, 0x265b, 0x10b5, 0x2866];
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0a;
+const PAL_TRN: u8 = 0x0b;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Mask {
+    Cancel = 0,
+    // Keeps showing the last frame while the game prepares the next screen
+    Freeze = 1,
+    Black = 2,
+    Color0 = 3,
+}
+
+impl Mask {
+    fn from_u8(value: u8) -> Self {
+        match value & 0b11 {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            _ => Mask::Color0,
+        }
+    }
+}
+
+// SNES colors are 15-bit BGR
+pub fn rgb(color: u16) -> u32 {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1f) as u32;
+        value << 3 | value >> 2
+    };
+    channel(0) << 16 | channel(5) << 8 | channel(10)
+}
+
+fn word(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+// VRAM transfers copy the first 256 background tiles shown on screen
+fn transfer_data(vram: &[u8], lcdc: u8) -> Vec<u8> {
+    let map = if lcdc & 0b0000_1000 != 0 { 0x1c00 } else { 0x1800 };
+    let unsigned = lcdc & 0b0001_0000 != 0;
+    (0..TRANSFER_SIZE / 16)
+        .flat_map(|tile| {
+            let id = vram[map + (tile / ATTRIBUTE_WIDTH) * 32 + tile % ATTRIBUTE_WIDTH];
+            let address = if unsigned {
+                id as usize * 16
+            } else {
+                (0x1000 + id as i8 as i32 * 16) as usize
+            };
+            vram[address..address + 16].to_vec()
+        })
+        .collect()
+}
+
+/// Super Game Boy, receives command packets through P1 and draws the recolored screen inside a border.
+pub struct Sgb {
+    // Packet received bit by bit, LSB first, between a reset pulse and a stop bit
+    packet: [u8; 16],
+    bit: usize,
+    receiving: bool,
+    p1: u8,
+    // Packets of a multi-packet command received so far
+    command: Vec<u8>,
+    // Color 0 is shared by the four palettes
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    attributes: [u8; ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT],
+    pub mask: Mask,
+    // Joypads requested by MLT_REQ, 1, 2 or 4
+    pub players: u8,
+    // 256 SNES 4bpp tiles and a 32x32 map, palettes 4-7 are used by the border
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    border_palettes: [[u16; 16]; 4],
+    back_shades: Vec<u8>,
+    shades: Vec<u8>,
+    pub frame: Vec<u32>,
+}
+
+impl Sgb {
+    pub fn default() -> Self {
+        let mut sgb = Self {
+            packet: [0; 16],
+            bit: 0,
+            receiving: false,
+            p1: 0x30,
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; SYSTEM_PALETTES * 4],
+            attributes: [0; ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT],
+            mask: Mask::Cancel,
+            players: 1,
+            border_tiles: vec![0; 0x2000],
+            border_map: vec![0; 32 * 32],
+            border_palettes: [[0; 16]; 4],
+            back_shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: vec![0; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT],
+        };
+        sgb.render();
+        sgb
+    }
+
+    pub fn palette(&self, palette: usize) -> [u16; 4] {
+        self.palettes[palette]
+    }
+
+    pub fn attribute(&self, x: usize, y: usize) -> u8 {
+        self.attributes[y * ATTRIBUTE_WIDTH + x]
+    }
+
+    // Both lines low resets, then each pulse of P14 sends a 0 and of P15 a 1
+    pub fn write_joypad(&mut self, data: u8, vram: &[u8], lcdc: u8) {
+        let p1 = data & 0b0011_0000;
+        let previous = self.p1;
+        self.p1 = p1;
+        if p1 == previous {
+            return;
+        }
+        match p1 {
+            0x00 => {
+                self.receiving = true;
+                self.bit = 0;
+                self.packet = [0; 16];
+            }
+            0x10 | 0x20 if previous == 0x30 && self.receiving => {
+                self.receive_bit(p1 == 0x10, vram, lcdc)
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_bit(&mut self, one: bool, vram: &[u8], lcdc: u8) {
+        if self.bit == self.packet.len() * 8 {
+            self.receiving = false;
+            if !one {
+                self.receive_packet(vram, lcdc);
+            }
+            return;
+        }
+        if one {
+            self.packet[self.bit / 8] |= 1 << (self.bit % 8);
+        }
+        self.bit += 1;
+    }
+
+    // The low 3 bits of the first byte give the number of packets in the command
+    fn receive_packet(&mut self, vram: &[u8], lcdc: u8) {
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0b111).max(1) as usize;
+        if self.command.len() >= packets * self.packet.len() {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command, vram, lcdc);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8], vram: &[u8], lcdc: u8) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => self.pal_set(data),
+            PAL_TRN => {
+                let transfer = transfer_data(vram, lcdc);
+                for (color, offset) in self.system_palettes.iter_mut().zip((0..).step_by(2)) {
+                    *color = word(&transfer, offset);
+                }
+            }
+            CHR_TRN => {
+                let half = (data[1] & 0b1) as usize * TRANSFER_SIZE;
+                self.border_tiles[half..half + TRANSFER_SIZE]
+                    .copy_from_slice(&transfer_data(vram, lcdc));
+            }
+            PCT_TRN => {
+                let transfer = transfer_data(vram, lcdc);
+                for (entry, offset) in self.border_map.iter_mut().zip((0..).step_by(2)) {
+                    *entry = word(&transfer, offset);
+                }
+                for (palette, colors) in self.border_palettes.iter_mut().enumerate() {
+                    for (color, value) in colors.iter_mut().enumerate() {
+                        *value = word(&transfer, 0x800 + palette * 32 + color * 2);
+                    }
+                }
+            }
+            MASK_EN => self.mask = Mask::from_u8(data[1]),
+            MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    0b01 => 2,
+                    0b11 => 4,
+                    _ => 1,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_shared_color(&mut self, color: u16) {
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color;
+        }
+    }
+
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        self.set_shared_color(word(data, 1));
+        for color in 1..4 {
+            self.palettes[first][color] = word(data, 1 + color * 2);
+            self.palettes[second][color] = word(data, 7 + color * 2);
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < ATTRIBUTE_WIDTH && y < ATTRIBUTE_HEIGHT {
+            self.attributes[y * ATTRIBUTE_WIDTH + x] = palette & 0b11;
+        }
+    }
+
+    // Each data set colors the inside, the border and the outside of a rectangle
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let control = set[0] & 0b111;
+            let inside = set[1] & 0b11;
+            let outside = (set[1] >> 4) & 0b11;
+            // With only the inside or the outside chosen, the border takes the same palette
+            let border = match control {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                _ if control & 0b010 != 0 => Some((set[1] >> 2) & 0b11),
+                _ => None,
+            };
+            let (x1, y1, x2, y2) = (
+                (set[2] & 0x1f) as usize,
+                (set[3] & 0x1f) as usize,
+                (set[4] & 0x1f) as usize,
+                (set[5] & 0x1f) as usize,
+            );
+            for y in 0..ATTRIBUTE_HEIGHT {
+                for x in 0..ATTRIBUTE_WIDTH {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_border = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_border {
+                        border
+                    } else if within && control & 0b001 != 0 {
+                        Some(inside)
+                    } else if !within && control & 0b100 != 0 {
+                        Some(outside)
+                    } else {
+                        None
+                    };
+                    if let Some(palette) = palette {
+                        self.set_attribute(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for line in data[2..].iter().take(count) {
+            let position = (line & 0x1f) as usize;
+            let palette = (line >> 5) & 0b11;
+            if line & 0x80 != 0 {
+                for x in 0..ATTRIBUTE_WIDTH {
+                    self.set_attribute(x, position, palette);
+                }
+            } else {
+                for y in 0..ATTRIBUTE_HEIGHT {
+                    self.set_attribute(position, y, palette);
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0b11;
+        let before = (data[1] >> 2) & 0b11;
+        let on_line = (data[1] >> 4) & 0b11;
+        let horizontal = data[1] & 0x40 != 0;
+        let position = (data[2] & 0x1f) as usize;
+        for y in 0..ATTRIBUTE_HEIGHT {
+            for x in 0..ATTRIBUTE_WIDTH {
+                let coordinate = if horizontal { y } else { x };
+                let palette = match coordinate.cmp(&position) {
+                    Ordering::Less => before,
+                    Ordering::Equal => on_line,
+                    Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    // Four palettes per byte, MSB first, wrapping at the edges of the screen
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = (data[1] & 0x1f) as usize;
+        let mut y = (data[2] & 0x1f) as usize;
+        let count = (word(data, 3) as usize).min(ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT);
+        let vertical = data[5] & 0b1 == 1;
+        for cell in 0..count {
+            let byte = match data.get(6 + cell / 4) {
+                Some(byte) => *byte,
+                None => break,
+            };
+            self.set_attribute(x, y, byte >> (6 - (cell % 4) * 2));
+            if vertical {
+                y += 1;
+                if y >= ATTRIBUTE_HEIGHT {
+                    y = 0;
+                    x = (x + 1) % ATTRIBUTE_WIDTH;
+                }
+            } else {
+                x += 1;
+                if x >= ATTRIBUTE_WIDTH {
+                    x = 0;
+                    y = (y + 1) % ATTRIBUTE_HEIGHT;
+                }
+            }
+        }
+    }
+
+    // Picks four of the palettes sent by PAL_TRN, attribute files are not supported
+    fn pal_set(&mut self, data: &[u8]) {
+        for palette in 0..4 {
+            let index = (word(data, 1 + palette * 2) & 0x1ff) as usize;
+            self.palettes[palette]
+                .copy_from_slice(&self.system_palettes[index * 4..index * 4 + 4]);
+        }
+        self.set_shared_color(self.palettes[0][0]);
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    /// Shades (0-3) of a line drawn by the PPU, after the DMG palette is applied.
+    pub fn draw_line(&mut self, ly: u8, shades: &[u8]) {
+        let start = ly as usize * SCREEN_WIDTH;
+        if let Some(line) = self.back_shades.get_mut(start..start + SCREEN_WIDTH) {
+            line.copy_from_slice(&shades[..SCREEN_WIDTH]);
+        }
+    }
+
+    pub fn complete_frame(&mut self, lcd_on: bool) {
+        if !lcd_on {
+            self.back_shades.iter_mut().for_each(|shade| *shade = 0);
+        }
+        if self.mask != Mask::Freeze {
+            self.shades.copy_from_slice(&self.back_shades);
+        }
+        self.render();
+    }
+
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u32> {
+        let entry = self.border_map[(y / 8) * 32 + x / 8];
+        let tile = (entry & 0xff) as usize;
+        let palette = ((entry >> 10) & 0b11) as usize;
+        let column = if entry & 0x4000 != 0 { 7 - x % 8 } else { x % 8 };
+        let row = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        // Bitplanes 0 and 1 are interleaved in the first 16 bytes, 2 and 3 in the last 16
+        let base = tile * 32 + row * 2;
+        let color = (0..4).fold(0, |color, plane| {
+            let byte = self.border_tiles[base + (plane / 2) * 16 + plane % 2];
+            color | ((byte >> (7 - column)) & 0b1) << plane
+        });
+        match color {
+            0 => None,
+            color => Some(rgb(self.border_palettes[palette][color as usize])),
+        }
+    }
+
+    fn screen_pixel(&self, x: usize, y: usize) -> u32 {
+        match self.mask {
+            Mask::Black => 0,
+            Mask::Color0 => rgb(self.palettes[0][0]),
+            _ => {
+                let palette = self.attribute(x / 8, y / 8) as usize;
+                rgb(self.palettes[palette][self.shades[y * SCREEN_WIDTH + x] as usize])
+            }
+        }
+    }
+
+    // The border is drawn over the screen, its transparent pixels show the backdrop
+    fn render(&mut self) {
+        let backdrop = rgb(self.palettes[0][0]);
+        for y in 0..SGB_SCREEN_HEIGHT {
+            for x in 0..SGB_SCREEN_WIDTH {
+                let on_screen = (SCREEN_X..SCREEN_X + SCREEN_WIDTH).contains(&x)
+                    && (SCREEN_Y..SCREEN_Y + SCREEN_HEIGHT).contains(&y);
+                self.frame[y * SGB_SCREEN_WIDTH + x] = match self.border_pixel(x, y) {
+                    Some(color) => color,
+                    None if on_screen => self.screen_pixel(x - SCREEN_X, y - SCREEN_Y),
+                    None => backdrop,
+                };
+            }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.packet);
+        state.write_u8(self.bit as u8);
+        state.write_bool(self.receiving);
+        state.write_u8(self.p1);
+        state.write_bytes(&self.command);
+        let colors = self.palettes.iter().flatten();
+        let border_colors = self.border_palettes.iter().flatten();
+        for color in colors.chain(border_colors).chain(self.system_palettes.iter()) {
+            state.write_u16(*color);
+        }
+        for entry in self.border_map.iter() {
+            state.write_u16(*entry);
+        }
+        state.write_bytes(&self.attributes);
+        state.write_u8(self.mask as u8);
+        state.write_u8(self.players);
+        state.write_bytes(&self.border_tiles);
+        state.write_bytes(&self.back_shades);
+        state.write_bytes(&self.shades);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+        state.read_into(&mut self.packet)?;
+        self.bit = state.read_u8()? as usize;
+        self.receiving = state.read_bool()?;
+        self.p1 = state.read_u8()?;
+        self.command = state.read_bytes()?;
+        let colors = self.palettes.iter_mut().flatten();
+        let border_colors = self.border_palettes.iter_mut().flatten();
+        for color in colors
+            .chain(border_colors)
+            .chain(self.system_palettes.iter_mut())
+        {
+            *color = state.read_u16()?;
+        }
+        for entry in self.border_map.iter_mut() {
+            *entry = state.read_u16()?;
+        }
+        state.read_into(&mut self.attributes)?;
+        self.mask = Mask::from_u8(state.read_u8()?);
+        self.players = state.read_u8()?;
+        state.read_into(&mut self.border_tiles)?;
+        state.read_into(&mut self.back_shades)?;
+        state.read_into(&mut self.shades)?;
+        self.render();
+        Ok(())
+    }
+}
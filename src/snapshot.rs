@@ -0,0 +1,176 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SGBS";
+const SNAPSHOT_VERSION: u8 = 1;
+
+#[derive(PartialEq, Debug)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    SizeMismatch,
+    InvalidValue,
+    RomMismatch,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::SizeMismatch => write!(f, "snapshot does not match the emulator layout"),
+            SnapshotError::InvalidValue => write!(f, "snapshot contains an invalid value"),
+            SnapshotError::RomMismatch => write!(f, "snapshot was taken with a different ROM"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn default() -> Self {
+        let mut data = SNAPSHOT_MAGIC.to_vec();
+        data.push(SNAPSHOT_VERSION);
+        Self { data }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Length prefixed, so the reader can check it against the destination
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, SnapshotError> {
+        if data.len() < 5 {
+            return Err(SnapshotError::Truncated);
+        }
+        if &data[0..4] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if data[4] != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(data[4]));
+        }
+        Ok(Self { data, cursor: 5 })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.cursor + len;
+        if end > self.data.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        let bytes = &self.data[self.cursor..end];
+        self.cursor = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidValue),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(LittleEndian::read_u16(self.take(2)?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(LittleEndian::read_u64(self.take(8)?))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, SnapshotError> {
+        Ok(LittleEndian::read_i64(self.take(8)?))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, SnapshotError> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    // Reads a length prefixed block into a fixed size buffer
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), SnapshotError> {
+        let len = self.read_u32()? as usize;
+        if len != buffer.len() {
+            return Err(SnapshotError::SizeMismatch);
+        }
+        buffer.copy_from_slice(self.take(len)?);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), SnapshotError> {
+        if self.cursor != self.data.len() {
+            return Err(SnapshotError::SizeMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_state_round_trip() {
+    let mut writer = StateWriter::default();
+    writer.write_u8(0x12);
+    writer.write_bool(true);
+    writer.write_u16(0x3456);
+    writer.write_u64(0x0123_4567_89ab_cdef);
+    writer.write_bytes(&[1, 2, 3]);
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data).unwrap();
+    assert_eq!(reader.read_u8(), Ok(0x12));
+    assert_eq!(reader.read_bool(), Ok(true));
+    assert_eq!(reader.read_u16(), Ok(0x3456));
+    assert_eq!(reader.read_u64(), Ok(0x0123_4567_89ab_cdef));
+    let mut buffer = [0; 2];
+    assert_eq!(reader.read_into(&mut buffer), Err(SnapshotError::SizeMismatch));
+    assert!(StateReader::new(&data[1..]).is_err());
+}
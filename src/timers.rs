@@ -1,13 +1,22 @@
-use super::dispatcher::Action;
+use super::constants::*;
 use super::emulator::Emulator;
+use super::interrupts::{request_interrupt, Interrupts};
+use super::snapshot::{SnapshotError, StateReader, StateWriter};
 use std::fmt;
 
 pub struct Timers {
     pub divider_frequency: u32,
     pub scan_line_counter: u32,
+    // Set while the LCD is off, for the shortened first line and hidden first frame
+    pub lcd_first_line: bool,
+    pub lcd_first_frame: bool,
     pub ime: bool,
     pub is_halted: bool,
     pub halt_bug: bool,
+    // Set by STOP, cleared when a joypad line goes low
+    pub is_stopped: bool,
+    // Set by illegal opcodes, the CPU stops fetching until reset
+    pub is_locked: bool,
 }
 
 impl Timers {
@@ -15,39 +24,176 @@ impl Timers {
         let divider_frequency = 16384;
         Self {
             scan_line_counter: 0,
+            lcd_first_line: false,
+            lcd_first_frame: false,
             divider_frequency,
             ime: false,
             is_halted: false,
             halt_bug: false,
+            is_stopped: false,
+            is_locked: false,
         }
     }
     pub fn clear_ime(&mut self) {
         self.ime = false;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.divider_frequency);
+        state.write_u32(self.scan_line_counter);
+        state.write_bool(self.lcd_first_line);
+        state.write_bool(self.lcd_first_frame);
+        state.write_bool(self.ime);
+        state.write_bool(self.is_halted);
+        state.write_bool(self.halt_bug);
+        state.write_bool(self.is_stopped);
+        state.write_bool(self.is_locked);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+        self.divider_frequency = state.read_u32()?;
+        self.scan_line_counter = state.read_u32()?;
+        self.lcd_first_line = state.read_bool()?;
+        self.lcd_first_frame = state.read_bool()?;
+        self.ime = state.read_bool()?;
+        self.is_halted = state.read_bool()?;
+        self.halt_bug = state.read_bool()?;
+        self.is_stopped = state.read_bool()?;
+        self.is_locked = state.read_bool()?;
+        Ok(())
+    }
 }
 
-pub fn update_div_counter(ctx: &mut Emulator) {
-    let div_counter = ctx.memory.get_div_counter();
-    let result = div_counter.wrapping_add(4);
-    ctx.memory.set_div_counter(result);
+/// DIV, TIMA, TMA and TAC. TIMA is clocked by the falling edge of the selected
+/// divider bit AND'ed with the enable bit, so any write that drops that signal
+/// increments TIMA just like a tick would.
+pub struct Timer {
+    divider: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    prev_output: bool,
+    // TIMA overflowed on the previous M-cycle and reads 0 until it's reloaded
+    overflow: bool,
+    // TIMA was reloaded on this M-cycle, TIMA writes are ignored and TMA writes go through
+    reloading: bool,
 }
 
-pub fn update_tima(ctx: &mut Emulator) {
-    let selected_bit = ctx.memory.get_div_counter() >> ctx.memory.tac_freq() & 0b1;
-    let bit_enabled = selected_bit & ctx.memory.tac_enabled();
-    if ((bit_enabled ^ 0b1) & ctx.memory.prev_timer_bit) == 1 {
-        let new_tima = ctx.memory.get_tima().wrapping_add(1);
-        if new_tima == 0 {
-            ctx.dispatcher.dispatch(Action::reload_tima(true));
+impl Timer {
+    pub fn default() -> Self {
+        Self {
+            divider: 0xab00,
+            tima: 0xcc,
+            tma: 0,
+            tac: 0,
+            prev_output: false,
+            overflow: false,
+            reloading: false,
         }
-        ctx.memory.set_tima(new_tima);
     }
-    ctx.memory.prev_timer_bit = bit_enabled;
+
+    /// Internal 16-bit counter, the APU frame sequencer and the serial clock
+    /// are driven by the falling edges of its bits.
+    pub fn divider(&self) -> u16 {
+        self.divider
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIVIDER_COUNTER_ADDRESS => (self.divider >> 8) as u8,
+            TIMER_COUNTER_ADDRESS => self.tima,
+            TIMER_MODULO_ADDRESS => self.tma,
+            TIMER_CONTROL_ADDRESS => 0b1111_1000 | self.tac,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            DIVIDER_COUNTER_ADDRESS => self.divider = 0,
+            TIMER_COUNTER_ADDRESS => {
+                if !self.reloading {
+                    self.tima = data;
+                    // Writing during the overflow cycle cancels the reload
+                    self.overflow = false;
+                }
+            }
+            TIMER_MODULO_ADDRESS => {
+                self.tma = data;
+                if self.reloading {
+                    self.tima = data;
+                }
+            }
+            TIMER_CONTROL_ADDRESS => self.tac = data & 0b111,
+            _ => unreachable!(),
+        }
+        self.update_output();
+    }
+
+    /// Advances one M-cycle, returns true when the timer interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        self.reloading = false;
+        let interrupt = self.overflow;
+        if self.overflow {
+            self.overflow = false;
+            self.tima = self.tma;
+            self.reloading = true;
+        }
+        self.divider = self.divider.wrapping_add(4);
+        self.update_output();
+        interrupt
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tac & 0b100 != 0
+    }
+
+    pub fn selected_bit(&self) -> u8 {
+        match self.tac & 0b11 {
+            0 => 9, // freq 4096 / 1024
+            1 => 3, // freq 262144 / 16
+            2 => 5, // freq 65536 / 64
+            3 => 7, // freq 16382 / 256
+            _ => unreachable!(),
+        }
+    }
+
+    fn update_output(&mut self) {
+        let output = self.is_enabled() && (self.divider >> self.selected_bit()) & 1 == 1;
+        if self.prev_output && !output {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflow |= overflow;
+        }
+        self.prev_output = output;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.divider);
+        state.write_u8(self.tima);
+        state.write_u8(self.tma);
+        state.write_u8(self.tac);
+        state.write_bool(self.prev_output);
+        state.write_bool(self.overflow);
+        state.write_bool(self.reloading);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SnapshotError> {
+        self.divider = state.read_u16()?;
+        self.tima = state.read_u8()?;
+        self.tma = state.read_u8()?;
+        self.tac = state.read_u8()?;
+        self.prev_output = state.read_bool()?;
+        self.overflow = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        Ok(())
+    }
 }
 
 pub fn update(ctx: &mut Emulator) {
-    update_div_counter(ctx);
-    update_tima(ctx);
+    if ctx.memory.timer.tick() {
+        request_interrupt(ctx, Interrupts::Timer as u8);
+    }
 }
 
 impl fmt::Debug for Timers {
@@ -56,8 +202,58 @@ impl fmt::Debug for Timers {
             f,
             "TIMERS ------------------------\n\
             MASTER ENABLED: {}\n\
-            IS HALTED: {}\n",
-            self.ime, self.is_halted
+            IS HALTED: {}\n\
+            IS STOPPED: {}\n\
+            IS LOCKED: {}\n",
+            self.ime, self.is_halted, self.is_stopped, self.is_locked
         )
     }
 }
+
+#[test]
+fn test_timer_write_glitches() {
+    let mut timer = Timer::default();
+    timer.write(TIMER_COUNTER_ADDRESS, 0);
+    // Bit 9 is set in the initial divider
+    timer.write(TIMER_CONTROL_ADDRESS, 0b100);
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 0);
+    // Disabling the timer drops the signal
+    timer.write(TIMER_CONTROL_ADDRESS, 0b000);
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 1);
+    // Resetting DIV too
+    timer.write(TIMER_CONTROL_ADDRESS, 0b100);
+    timer.write(DIVIDER_COUNTER_ADDRESS, 0);
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 2);
+    assert_eq!(timer.divider(), 0);
+}
+
+#[test]
+fn test_timer_overflow() {
+    let mut timer = Timer::default();
+    timer.write(DIVIDER_COUNTER_ADDRESS, 0);
+    timer.write(TIMER_MODULO_ADDRESS, 0x42);
+    timer.write(TIMER_COUNTER_ADDRESS, 0xff);
+    timer.write(TIMER_CONTROL_ADDRESS, 0b101);
+    // Bit 3 falls every 16 cycles
+    for _ in 0..4 {
+        assert!(!timer.tick());
+    }
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 0);
+    assert!(timer.tick());
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 0x42);
+    // TIMA writes are ignored during the reload cycle
+    timer.write(TIMER_COUNTER_ADDRESS, 0x10);
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 0x42);
+
+    // Writing TIMA during the overflow cycle cancels the reload
+    let mut timer = Timer::default();
+    timer.write(DIVIDER_COUNTER_ADDRESS, 0);
+    timer.write(TIMER_COUNTER_ADDRESS, 0xff);
+    timer.write(TIMER_CONTROL_ADDRESS, 0b101);
+    for _ in 0..4 {
+        timer.tick();
+    }
+    timer.write(TIMER_COUNTER_ADDRESS, 0x20);
+    assert!(!timer.tick());
+    assert_eq!(timer.read(TIMER_COUNTER_ADDRESS), 0x20);
+}
@@ -1,41 +1,54 @@
-use soup_gb::cpu;
+use soup_gb::cartridge::header::{CgbSupport, Destination, RomHeader};
+use soup_gb::cartridge::{LoadError, NINTENDO_LOGO};
+use soup_gb::clock::FixedClock;
+use soup_gb::constants::CPU_CLOCK_HZ;
 use soup_gb::dispatcher::Action;
-use soup_gb::emulator::Emulator;
-use soup_gb::interrupts;
-use soup_gb::memory::LcdMode;
+use soup_gb::emulator::{Emulator, Event};
+use soup_gb::headless::HeadlessRunner;
+use soup_gb::image_source::{StaticImage, CAMERA_HEIGHT, CAMERA_WIDTH};
+use soup_gb::joypad::Button;
+use soup_gb::memory::{LcdMode, Model};
+use soup_gb::movie::{Movie, StartState};
+use soup_gb::printer::Printer;
+use soup_gb::screenshot::save_png;
+use soup_gb::sgb::Mask;
 use soup_gb::utils::*;
 
+// Pads the ROM to 32KB and fixes the header checksum so it passes validation
+fn with_header(mut rom: Vec<u8>) -> Vec<u8> {
+  rom.resize(0x8000, 0);
+  rom[0x14d] = rom[0x134..=0x14c]
+    .iter()
+    .fold(0u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1));
+  rom
+}
+
 fn assert_pc_byte_and_sp(emulator: &mut Emulator, pc: u16, byte: u8, sp: u8) {
   assert_eq!(emulator.memory.get_pc(), pc);
   assert_eq!(emulator.memory.get_byte_debug(), byte);
   assert_eq!(emulator.memory.read(emulator.memory.get_sp()), sp);
 }
 
-fn run_cpu(ctx: &mut Emulator) {
-  interrupts::update(ctx);
-  cpu::update(ctx);
-}
-
 #[test]
 fn line_0_timing_test() {
   // Mode 2 test
   let rom = vec![0; 0x200];
 
   let mut emulator = Emulator::default();
-  emulator.load_rom(rom);
+  emulator.load_rom(with_header(rom)).unwrap();
 
   while emulator.memory.get_ly() != 0 || emulator.memory.lcd_mode() != LcdMode::ReadOAM {
-    run_cpu(&mut emulator);
+    emulator.step_instruction();
   }
   assert_eq!(emulator.memory.get_ly(), 0);
   assert_eq!(emulator.memory.lcd_mode(), LcdMode::ReadOAM);
   assert_eq!(emulator.timers.scan_line_counter, 4);
   for _ in (4..80).step_by(4) {
-    run_cpu(&mut emulator);
+    emulator.step_instruction();
     assert_eq!(emulator.memory.get_ly(), 0);
     assert_eq!(emulator.memory.lcd_mode(), LcdMode::ReadOAM);
   }
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert_eq!(emulator.memory.get_ly(), 0);
   assert_eq!(emulator.memory.lcd_mode(), LcdMode::ReadVRAM);
 }
@@ -45,10 +58,10 @@ fn line_144_timing_test() {
   let rom = vec![0; 0x200];
 
   let mut emulator = Emulator::default();
-  emulator.load_rom(rom);
+  emulator.load_rom(with_header(rom)).unwrap();
 
   while emulator.memory.get_ly() < 144 {
-    run_cpu(&mut emulator);
+    emulator.step_instruction();
   }
   // clock 0
   emulator.memory.write(0xffff, 0b1110_0001); // v-blank interrupt enabled
@@ -58,7 +71,7 @@ fn line_144_timing_test() {
   assert_eq!(emulator.memory.lcd_mode(), LcdMode::HBlank);
   assert!(!get_bit_at(emulator.memory.read(0xff0f), 0));
   // clock 4
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert!(emulator.timers.ime);
   assert_eq!(emulator.memory.get_ly(), 144);
   assert_eq!(emulator.memory.lcd_mode(), LcdMode::VBlank);
@@ -66,13 +79,13 @@ fn line_144_timing_test() {
   // clock 8-452
   // 456 - 8 (previous 2 clocks) - 20 (interrupt takes 5 cycles)
   for _ in (0..428).step_by(4) {
-    run_cpu(&mut emulator);
+    emulator.step_instruction();
     assert_eq!(emulator.memory.get_ly(), 144);
     assert_eq!(emulator.memory.lcd_mode(), LcdMode::VBlank);
     assert!(!get_bit_at(emulator.memory.read(0xff0f), 0));
   }
   // next line
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert_eq!(emulator.memory.get_ly(), 145);
 }
 
@@ -82,35 +95,35 @@ fn halt_ime_1() {
   let rom = [boot, vec![0xfb, 0x00, 0x76, 0x3c, 0x04], vec![0; 0x47]].concat();
 
   let mut emulator = Emulator::default();
-  emulator.load_rom(rom);
+  emulator.load_rom(with_header(rom)).unwrap();
 
   // IME=1
   assert_pc_byte_and_sp(&mut emulator, 0x100, 0xfb, 0x00);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert!(!emulator.timers.ime); // Master enabled in next cycle
 
   assert_pc_byte_and_sp(&mut emulator, 0x101, 0x00, 0x00);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert!(emulator.timers.ime); // Master enabled here
 
   assert_pc_byte_and_sp(&mut emulator, 0x102, 0x76, 0x00);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   // Enable IE for VBlank
   emulator.memory.write(0xffff, 0b0000_0001);
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   // Enable IF for VBlank
   emulator.memory.write(0xff0f, 0b0000_0001);
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   assert_eq!(emulator.memory.get_pc(), 0x40);
   assert!(!emulator.timers.is_halted);
@@ -125,55 +138,1301 @@ fn halt_ime_0() {
   let rom = [boot, vec![0xf3, 0x00, 0x76, 0x3c, 0x04], vec![0; 0x47]].concat();
 
   let mut emulator = Emulator::default();
-  emulator.load_rom(rom);
+  emulator.load_rom(with_header(rom)).unwrap();
 
   // IME=0
   assert_pc_byte_and_sp(&mut emulator, 0x100, 0xf3, 0x00);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert!(!emulator.timers.ime); // Master disabled in next cycle
 
   assert_pc_byte_and_sp(&mut emulator, 0x101, 0x00, 0x00);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
   assert!(!emulator.timers.ime); // Master disabled here
 
   assert_pc_byte_and_sp(&mut emulator, 0x102, 0x76, 0x00);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   // HALT mode entered
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   // Enable IE for VBlank
   emulator.memory.write(0xffff, 0b0000_0001);
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
   // Enable IF for VBlank
   emulator.memory.write(0xff0f, 0b0000_0001);
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert!(emulator.timers.is_halted);
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
+  // Woken up without servicing the interrupt
   assert!(!emulator.timers.is_halted);
   assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
   assert_eq!(emulator.memory.read(0xff0f) & 0b0000_0001, 1); // IF not cleared
-  run_cpu(&mut emulator);
+  emulator.step_instruction();
 
-  // Halt bug
   assert!(!emulator.timers.is_halted);
-  assert_pc_byte_and_sp(&mut emulator, 0x103, 0x3c, 0x00);
-  run_cpu(&mut emulator);
+  assert_pc_byte_and_sp(&mut emulator, 0x104, 0x04, 0x00);
+  assert_eq!(emulator.registers.a, 0x02);
+}
+
+#[test]
+fn halt_bug() {
+  let boot = vec![0; 0x100];
+  let rom = [boot, vec![0xf3, 0x76, 0x3c, 0x04], vec![0; 0x48]].concat();
+
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  emulator.step_instruction();
 
+  // IME=0 with an interrupt pending
+  emulator.memory.write(0xffff, 0b0000_0001);
+  emulator.memory.write(0xff0f, 0b0000_0001);
+  assert_pc_byte_and_sp(&mut emulator, 0x101, 0x76, 0x00);
+  emulator.step_instruction();
+
+  // HALT is skipped, INC A runs twice
   assert!(!emulator.timers.is_halted);
-  assert_pc_byte_and_sp(&mut emulator, 0x104, 0x04, 0x00);
+  assert_pc_byte_and_sp(&mut emulator, 0x102, 0x3c, 0x00);
+  emulator.step_instruction();
+  assert_pc_byte_and_sp(&mut emulator, 0x102, 0x3c, 0x00);
+  emulator.step_instruction();
+  assert_pc_byte_and_sp(&mut emulator, 0x103, 0x04, 0x00);
+  assert_eq!(emulator.registers.a, 0x03);
+}
+
+#[test]
+fn run_frame_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]); // JR -2
+
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+
+  emulator.run_frame();
+  assert_eq!(emulator.frame_count, 1);
+  assert_eq!(emulator.memory.get_ly(), 144);
+  let start = emulator.cycles;
+  emulator.run_frame();
+  assert_eq!(emulator.frame_count, 2);
+  assert_eq!(emulator.cycles - start, 70224);
+
+  let cycles = emulator.run_cycles(456);
+  assert_eq!(cycles, 456);
+  assert_eq!(emulator.memory.get_ly(), 145);
+
+  emulator.run_until(|ctx| ctx.memory.get_ly() == 0);
+  assert_eq!(emulator.frame_count, 2);
+}
+
+fn stop_rom() -> Vec<u8> {
+  let mut rom = vec![0; 0x200];
+  let program = [
+    0x3e, 0x20, // LD A, 0x20
+    0xe0, 0x00, // LDH (0x00), A
+    0x10, 0x00, // STOP
+    0x04, // INC B
+    0x18, 0xfd, // JR -3
+  ];
+  rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+  with_header(rom)
+}
+
+#[test]
+fn stop_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(stop_rom()).unwrap();
+  for _ in 0..3 {
+    emulator.step_instruction();
+  }
+  assert!(emulator.timers.is_stopped);
+  assert_eq!(emulator.memory.get_pc(), 0x106);
+  assert_eq!(emulator.memory.get_div(), 0);
+
+  // CPU, DIV and PPU are frozen
+  let ly = emulator.memory.get_ly();
+  let counter = emulator.timers.scan_line_counter;
+  emulator.run_cycles(10_000);
+  assert_eq!(emulator.memory.get_pc(), 0x106);
+  assert_eq!(emulator.memory.get_div(), 0);
+  assert_eq!(emulator.memory.get_ly(), ly);
+  assert_eq!(emulator.timers.scan_line_counter, counter);
+  let frame_count = emulator.frame_count;
+  emulator.run_frame();
+  assert_eq!(emulator.frame_count, frame_count + 1);
+
+  // Buttons on a deselected row do not wake the CPU
+  emulator.press(Button::A);
+  emulator.step_instruction();
+  assert!(emulator.timers.is_stopped);
+
+  emulator.press(Button::Down);
+  emulator.step_instruction();
+  assert!(!emulator.timers.is_stopped);
+  emulator.step_instruction();
+  assert_eq!(emulator.registers.b, 1);
+}
+
+#[test]
+fn stop_held_button_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(stop_rom()).unwrap();
+  // A line already low when STOP runs does not wake the CPU
+  emulator.press(Button::Down);
+  for _ in 0..3 {
+    emulator.step_instruction();
+  }
+  emulator.run_cycles(1_000);
+  assert!(emulator.timers.is_stopped);
+
+  emulator.release(Button::Down);
+  emulator.step_instruction();
+  assert!(emulator.timers.is_stopped);
+  emulator.press(Button::Down);
+  emulator.step_instruction();
+  assert!(!emulator.timers.is_stopped);
+}
+
+#[test]
+fn speed_switch_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(stop_rom()).unwrap();
+  assert_eq!(emulator.memory.read(0xff4d), 0xff);
+
+  emulator.memory.model = Model::Cgb;
+  emulator.memory.write(0xff4d, 0x01);
+  assert_eq!(emulator.memory.read(0xff4d), 0x7f);
+  for _ in 0..3 {
+    emulator.step_instruction();
+  }
+  assert!(!emulator.timers.is_stopped);
+  assert!(emulator.memory.double_speed);
+  assert_eq!(emulator.memory.read(0xff4d), 0xfe);
+  assert_eq!(emulator.memory.get_pc(), 0x106);
+}
+
+// Counts the STAT interrupt requests raised over a whole frame
+fn count_stat_interrupts(emulator: &mut Emulator) -> u32 {
+  let mut count = 0;
+  let start = emulator.cycles;
+  while emulator.cycles - start < 70224 {
+    emulator.step_instruction();
+    let i_f = emulator.memory.read(0xff0f);
+    if get_bit_at(i_f, 1) {
+      count += 1;
+      emulator.memory.write(0xff0f, clear_bit_at(i_f, 1));
+    }
+  }
+  count
+}
+
+#[test]
+fn stat_line_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]); // JR -2
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  emulator.run_frame();
+
+  // HBlank only
+  emulator.memory.write(0xff41, 0b0000_1000);
+  count_stat_interrupts(&mut emulator);
+  assert_eq!(count_stat_interrupts(&mut emulator), 144);
+
+  // HBlank is followed by OAM without the line going low, only line 0 adds an edge
+  emulator.memory.write(0xff41, 0b0010_1000);
+  count_stat_interrupts(&mut emulator);
+  assert_eq!(count_stat_interrupts(&mut emulator), 145);
+
+  // VBlank and the line 144 OAM condition share the same edge
+  emulator.memory.write(0xff41, 0b0011_0000);
+  count_stat_interrupts(&mut emulator);
+  assert_eq!(count_stat_interrupts(&mut emulator), 144);
+
+  // LY=LYC fires once per frame
+  emulator.memory.write(0xff45, 0x10);
+  emulator.memory.write(0xff41, 0b0100_0000);
+  count_stat_interrupts(&mut emulator);
+  assert_eq!(count_stat_interrupts(&mut emulator), 1);
+
+  // Writing LYC to the current line raises the line right away
+  emulator.memory.write(0xff0f, 0);
+  let ly = emulator.memory.get_ly();
+  emulator.memory.write(0xff45, ly);
+  assert!(get_bit_at(emulator.memory.read(0xff0f), 1));
+}
+
+#[test]
+fn stat_write_quirk_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]); // JR -2
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  emulator.memory.write(0xff45, 0x80);
+
+  // Spurious interrupt in VBlank
+  emulator.run_frame();
+  emulator.memory.write(0xff0f, 0);
+  emulator.memory.write(0xff41, 0);
+  assert!(get_bit_at(emulator.memory.read(0xff0f), 1));
+
+  // None during mode 2
+  emulator.run_until(|ctx| ctx.memory.lcd_mode() == LcdMode::ReadOAM);
+  emulator.memory.write(0xff0f, 0);
+  emulator.memory.write(0xff41, 0);
+  assert!(!get_bit_at(emulator.memory.read(0xff0f), 1));
+
+  // Nor on CGB
+  emulator.memory.model = Model::Cgb;
+  emulator.run_until(|ctx| ctx.memory.lcd_mode() == LcdMode::HBlank);
+  emulator.memory.write(0xff0f, 0);
+  emulator.memory.write(0xff41, 0);
+  assert!(!get_bit_at(emulator.memory.read(0xff0f), 1));
+}
+
+#[test]
+fn oam_dma_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100] = 0x3c;
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  emulator.memory.write(0xff40, 0x00);
+  for i in 0..0xa0 {
+    emulator.memory.write(0xc000 + i, i as u8);
+    emulator.memory.write(0xd000 + i, 0x80 | i as u8);
+  }
+  emulator.memory.write(0x8000, 0x42);
+  emulator.memory.write(0xff80, 0x24);
+
+  // Sources from 0xe000 read WRAM
+  emulator.memory.write(0xff46, 0xe0);
+  emulator.take_cycle();
+  // OAM is still reachable during the startup delay
+  assert_eq!(emulator.memory.read(0xfe00), 0x00);
+  emulator.take_cycle();
+  assert!(emulator.memory.is_dma_active());
+  assert_eq!(emulator.memory.read(0xfe00), 0xff);
+  // Conflicting reads see the transferred byte, the other bus and HRAM are free
+  assert_eq!(emulator.memory.read(0xd000), 0x00);
+  assert_eq!(emulator.memory.read(0x0100), 0x00);
+  assert_eq!(emulator.memory.read(0x8000), 0x42);
+  assert_eq!(emulator.memory.read(0xff80), 0x24);
+  emulator.take_cycle();
+  assert_eq!(emulator.memory.read(0xd000), 0x01);
+
+  // Restarting keeps the old transfer running until the new one starts
+  emulator.memory.write(0xff46, 0xd0);
+  emulator.take_cycle();
+  assert_eq!(emulator.memory.read(0xd000), 0x02);
+  for _ in 0..0xa0 {
+    assert_eq!(emulator.memory.read(0xfe00), 0xff);
+    emulator.take_cycle();
+  }
+  assert!(!emulator.memory.is_dma_active());
+  assert_eq!(emulator.memory.read(0x0100), 0x3c);
+  for i in 0..0xa0 {
+    assert_eq!(emulator.memory.read(0xfe00 + i), 0x80 | i as u8);
+  }
+}
+
+#[test]
+fn lcd_power_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]); // JR -2
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  emulator.run_until(|ctx| ctx.memory.get_ly() == 10);
+
+  // LY and mode read 0 while off, frames keep coming and are blank
+  emulator.memory.write(0xff40, 0x11);
+  emulator.step_instruction();
+  assert_eq!(emulator.memory.get_ly(), 0);
+  assert_eq!(emulator.memory.read(0xff41) & 0b11, 0);
+  let frame_count = emulator.frame_count;
+  let frame = emulator.run_frame();
+  assert!(frame.iter().all(|pixel| *pixel == 0xff_ff_ff));
+  assert_eq!(emulator.frame_count, frame_count + 1);
+
+  // The first line starts in mode 0 and skips mode 2
+  emulator.memory.write(0xff40, 0x91);
+  let start = emulator.cycles;
+  while emulator.memory.lcd_mode() == LcdMode::HBlank {
+    emulator.take_cycle();
+  }
+  assert_eq!(emulator.memory.lcd_mode(), LcdMode::ReadVRAM);
+  assert_eq!(emulator.cycles - start, 80);
+  while emulator.memory.get_ly() == 0 {
+    emulator.take_cycle();
+  }
+  assert_eq!(emulator.cycles - start, 452);
+  assert_eq!(emulator.memory.lcd_mode(), LcdMode::HBlank);
+
+  // The frame being drawn when the LCD was turned on is not shown
+  emulator.memory.write(0x8000, 0xff);
+  let frame = emulator.run_frame();
+  assert!(frame.iter().all(|pixel| *pixel == 0xff_ff_ff));
+  let frame = emulator.run_frame();
+  assert!(frame.iter().any(|pixel| *pixel != 0xff_ff_ff));
+}
+
+#[test]
+fn illegal_opcode_lockup_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100..0x102].copy_from_slice(&[0xfb, 0xdd]); // EI, illegal
+  let rom = with_header(rom);
+
+  let mut emulator = Emulator::default();
+  emulator.load_rom(rom.clone()).unwrap();
+  emulator.memory.write(0xffff, 0x01);
+
+  emulator.step_instruction();
+  emulator.step_instruction();
+  assert!(emulator.timers.is_locked);
+  assert_eq!(
+    emulator.take_events(),
+    vec![Event::CpuLocked {
+      opcode: 0xdd,
+      address: 0x101
+    }]
+  );
+
+  // The PPU keeps running, but VBlank is never serviced
+  let pc = emulator.memory.get_pc();
+  emulator.run_frame();
+  emulator.run_frame();
+  assert_eq!(emulator.frame_count, 2);
+  assert_eq!(emulator.memory.get_pc(), pc);
+  assert!(emulator.take_events().is_empty());
+
+  let state = emulator.save_state();
+  let mut restored = Emulator::default();
+  restored.load_rom(rom).unwrap();
+  restored.load_state(&state).unwrap();
+  assert!(restored.timers.is_locked);
+}
+
+#[test]
+fn unused_io_bits_test() {
+  let mut emulator = Emulator::default();
+  // Unmapped and CGB-only registers read 0xff on DMG
+  for address in [0xff03, 0xff08, 0xff15, 0xff27, 0xff4c, 0xff4d, 0xff4f, 0xff70, 0xff7f] {
+    emulator.memory.write(address, 0x00);
+    assert_eq!(emulator.memory.read(address), 0xff, "{:04X}", address);
+  }
+  emulator.memory.write(0xff00, 0x00);
+  assert_eq!(emulator.memory.read(0xff00) & 0xf0, 0xc0);
+  emulator.memory.write(0xff02, 0x00);
+  assert_eq!(emulator.memory.read(0xff02), 0x7e);
+  emulator.memory.write(0xff07, 0x00);
+  assert_eq!(emulator.memory.read(0xff07), 0xf8);
+  emulator.memory.write(0xff10, 0x00);
+  assert_eq!(emulator.memory.read(0xff10), 0x80);
+  emulator.memory.write(0xff13, 0x00);
+  assert_eq!(emulator.memory.read(0xff13), 0xff);
+  emulator.memory.write(0xff1c, 0x00);
+  assert_eq!(emulator.memory.read(0xff1c), 0x9f);
+  emulator.memory.write(0xff26, 0x00);
+  assert_eq!(emulator.memory.read(0xff26) & 0xf0, 0x70);
+  emulator.memory.write(0xff30, 0x00);
+  assert_eq!(emulator.memory.read(0xff30), 0x00);
+  emulator.memory.write(0xff41, 0x00);
+  assert_eq!(emulator.memory.read(0xff41) & 0xf8, 0x80);
+}
+
+fn read_rtc(emulator: &mut Emulator) -> [u8; 5] {
+  emulator.memory.write(0x6000, 0x00);
+  emulator.memory.write(0x6000, 0x01);
+  let mut registers = [0; 5];
+  for (index, register) in registers.iter_mut().enumerate() {
+    emulator.memory.write(0x4000, 0x08 + index as u8);
+    *register = emulator.memory.read(0xa000);
+  }
+  registers
+}
+
+#[test]
+fn mbc3_rtc_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]); // JR -2
+  rom[0x147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+  rom[0x149] = 0x02;
+  let rom = with_header(rom);
+
+  let mut emulator = Emulator::default();
+  emulator.load_rom(rom.clone()).unwrap();
+  emulator.memory.cartridge.set_clock(Box::new(FixedClock(1_000)));
+  emulator.memory.write(0x0000, 0x0a);
+  emulator.memory.write(0x4000, 0x08);
+  emulator.memory.write(0xa000, 58);
+  emulator.memory.write(0x4000, 0x09);
+  emulator.memory.write(0xa000, 59);
+
+  // Counts emulated time, not the wall clock
+  emulator.run_cycles(CPU_CLOCK_HZ as u64 * 2);
+  assert_eq!(read_rtc(&mut emulator), [0, 0, 1, 0, 0]);
+
+  // Halted clocks keep their value
+  emulator.memory.write(0x4000, 0x0c);
+  emulator.memory.write(0xa000, 0x40);
+  emulator.run_cycles(CPU_CLOCK_HZ as u64);
+  assert_eq!(read_rtc(&mut emulator), [0, 0, 1, 0, 0x40]);
+  emulator.memory.write(0xa000, 0x00);
+
+  emulator.memory.write(0x4000, 0x00);
+  emulator.memory.write(0xa000, 0x42);
+  let save = emulator.battery_save().unwrap();
+  assert_eq!(save.len(), 0x2000 + 48);
+
+  // A day and an hour passed between sessions
+  let mut restored = Emulator::default();
+  restored.load_rom(rom).unwrap();
+  restored.memory.cartridge.set_clock(Box::new(FixedClock(1_000 + 90_000)));
+  restored.load_battery_save(&save);
+  restored.memory.write(0x0000, 0x0a);
+  assert_eq!(read_rtc(&mut restored), [0, 0, 2, 1, 0]);
+  restored.memory.write(0x4000, 0x00);
+  assert_eq!(restored.memory.read(0xa000), 0x42);
+
+  // Out of range seconds and bogus save times neither overflow nor take ages to catch up
+  let mut save = save;
+  save[0x2000] = 61;
+  save[0x2000 + 40..].copy_from_slice(&i64::MIN.to_le_bytes());
+  restored.load_battery_save(&save);
+  assert_eq!(read_rtc(&mut restored)[0], 61);
+  save[0x2000 + 40..].copy_from_slice(&(-1_000_000_000_000_000_000i64).to_le_bytes());
+  restored.load_battery_save(&save);
+  assert!(read_rtc(&mut restored)[0] < 60);
+}
+
+// 1MB MBC1 ROM where every bank starts with its own number
+fn mbc1_8mbit_rom(multicart: bool) -> Vec<u8> {
+  let mut rom = vec![0; 0x100000];
+  for bank in 0..0x40 {
+    rom[bank * 0x4000] = bank as u8;
+  }
+  rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+  if multicart {
+    rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+  }
+  rom[0x147] = 0x01;
+  rom[0x148] = 0x05;
+  let header = with_header(rom[..0x8000].to_vec());
+  rom[..0x8000].copy_from_slice(&header);
+  rom
+}
+
+#[test]
+fn mbc1_multicart_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(mbc1_8mbit_rom(true)).unwrap();
+  emulator.memory.write(0x2000, 0x12);
+  emulator.memory.write(0x4000, 0x01);
+  // Only the low 4 bits of BANK1 reach the ROM
+  assert_eq!(emulator.memory.read(0x4000), 0x12);
+  emulator.memory.write(0x4000, 0x03);
+  assert_eq!(emulator.memory.read(0x4000), 0x32);
+  emulator.memory.write(0x6000, 0x01);
+  assert_eq!(emulator.memory.read(0x0000), 0x30);
+  // BANK1 is still 5 bits wide when checking for zero
+  emulator.memory.write(0x2000, 0x10);
+  assert_eq!(emulator.memory.read(0x4000), 0x30);
+
+  emulator.load_rom(mbc1_8mbit_rom(false)).unwrap();
+  emulator.memory.write(0x2000, 0x12);
+  emulator.memory.write(0x4000, 0x01);
+  assert_eq!(emulator.memory.read(0x4000), 0x32);
+  emulator.memory.write(0x6000, 0x01);
+  assert_eq!(emulator.memory.read(0x0000), 0x20);
+}
+
+#[test]
+fn mmm01_test() {
+  // 128KB compilation, the menu header sits in the last 32KB
+  let mut rom = vec![0; 0x20000];
+  for bank in 0..8 {
+    rom[bank * 0x4000 + 0x3000] = bank as u8;
+  }
+  rom[0x147] = 0x01;
+  rom[0x148] = 0x02;
+  let header = with_header(rom[..0x8000].to_vec());
+  rom[..0x8000].copy_from_slice(&header);
+  // Without a valid menu header the last bank is only game code
+  rom[0x18147] = 0x0d;
+  let mut emulator = Emulator::default();
+  assert_eq!(emulator.load_rom(rom.clone()).unwrap().cartridge_type, 0x01);
+
+  rom[0x18104..0x18134].copy_from_slice(&NINTENDO_LOGO);
+  rom[0x18147] = 0x0d; // MMM01+RAM+BATTERY
+  rom[0x18148] = 0x02;
+  rom[0x18149] = 0x02;
+  let menu = with_header(rom[0x18000..].to_vec());
+  rom[0x18000..].copy_from_slice(&menu);
+  let info = emulator.load_rom(rom).unwrap();
+  assert_eq!(info.cartridge_type, 0x0d);
+  assert_eq!(info.ram_size, 0x2000);
+  assert_eq!(emulator.memory.read(0x3000), 0x06);
+  assert_eq!(emulator.memory.read(0x7000), 0x07);
+
+  // The menu picks the game at bank 2 and freezes ROM bank bits 1-2
+  emulator.memory.write(0x2000, 0x02);
+  emulator.memory.write(0x6000, 0b0011 << 2);
+  emulator.memory.write(0x0000, 0x4a);
+  assert_eq!(emulator.memory.read(0x3000), 0x02);
+  emulator.memory.write(0x2000, 0x01);
+  assert_eq!(emulator.memory.read(0x7000), 0x03);
+  emulator.memory.write(0x2000, 0x07);
+  assert_eq!(emulator.memory.read(0x7000), 0x03);
+
+  // Registers are locked once mapped
+  emulator.memory.write(0x0000, 0x0a);
+  emulator.memory.write(0x6000, 0x00);
+  assert_eq!(emulator.memory.read(0x3000), 0x02);
+
+  emulator.memory.write(0xa000, 0x42);
+  assert_eq!(emulator.memory.read(0xa000), 0x42);
+  assert_eq!(emulator.battery_save().unwrap().len(), 0x2000);
+}
+
+fn huc3_rom() -> Vec<u8> {
+  let mut rom = vec![0; 0x200];
+  rom[0x147] = 0xfe;
+  rom[0x149] = 0x02;
+  with_header(rom)
+}
+
+// Reads minutes and days back through the HuC3 command interface
+fn read_huc3_time(emulator: &mut Emulator) -> Vec<u8> {
+  emulator.memory.write(0x0000, 0x0b);
+  for command in [0x60, 0x40, 0x50] {
+    emulator.memory.write(0xa000, command);
+  }
+  (0..6)
+    .map(|_| {
+      emulator.memory.write(0x0000, 0x0b);
+      emulator.memory.write(0xa000, 0x10);
+      emulator.memory.write(0x0000, 0x0c);
+      emulator.memory.read(0xa000) & 0x0f
+    })
+    .collect()
+}
+
+#[test]
+fn huc3_rtc_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(huc3_rom()).unwrap();
+  emulator.memory.cartridge.set_clock(Box::new(FixedClock(1_000)));
+
+  // 59 minutes into day 2, written a nibble at a time
+  emulator.memory.write(0x0000, 0x0b);
+  for command in [0x40, 0x50, 0x3b, 0x33, 0x30, 0x32, 0x30, 0x30, 0x61] {
+    emulator.memory.write(0xa000, command);
+  }
+  emulator.memory.cartridge.tick(CPU_CLOCK_HZ * 60);
+  assert_eq!(read_huc3_time(&mut emulator), vec![0xc, 0x3, 0x0, 0x2, 0x0, 0x0]);
+
+  emulator.memory.write(0x0000, 0x0a);
+  emulator.memory.write(0xa000, 0x42);
+  let save = emulator.battery_save().unwrap();
+
+  let mut restored = Emulator::default();
+  restored.load_rom(huc3_rom()).unwrap();
+  restored.memory.cartridge.set_clock(Box::new(FixedClock(1_000 + 86_400)));
+  restored.load_battery_save(&save);
+  assert_eq!(read_huc3_time(&mut restored), vec![0xc, 0x3, 0x0, 0x3, 0x0, 0x0]);
+  restored.memory.write(0x0000, 0x0a);
+  assert_eq!(restored.memory.read(0xa000), 0x42);
+  // A bogus save time does not overflow the elapsed time
+  let mut save = save;
+  save[0x2000..0x2008].copy_from_slice(&i64::MIN.to_le_bytes());
+  restored.load_battery_save(&save);
+  assert_eq!(read_huc3_time(&mut restored), vec![0xc, 0x3, 0x0, 0x2, 0x0, 0x0]);
+}
+
+#[test]
+fn huc1_ir_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x147] = 0xff;
+  rom[0x149] = 0x03;
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+
+  emulator.memory.write(0xa000, 0x42);
+  emulator.memory.write(0x0000, 0x0e);
+  assert_eq!(emulator.memory.read(0xa000), 0xc0);
+  emulator.memory.cartridge.set_ir_light(true);
+  assert_eq!(emulator.memory.read(0xa000), 0xc1);
+  emulator.memory.write(0xa000, 0x01);
+  assert!(emulator.memory.cartridge.ir_led());
+
+  emulator.memory.write(0x0000, 0x00);
+  assert_eq!(emulator.memory.read(0xa000), 0x42);
+}
+
+#[test]
+fn pocket_camera_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x147] = 0xfc;
+  rom[0x149] = 0x04;
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+
+  // Left half black, right half white, round tripped through a PNG
+  let pixels: Vec<u32> = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+    .map(|i| if i % CAMERA_WIDTH < CAMERA_WIDTH / 2 { 0 } else { 0xff_ff_ff })
+    .collect();
+  let path = std::env::temp_dir().join("soup_gb_camera_test.png");
+  save_png(&path, &pixels, CAMERA_WIDTH, CAMERA_HEIGHT).unwrap();
+  let source = StaticImage::load(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+  emulator.memory.cartridge.set_image_source(Box::new(source));
+
+  emulator.memory.write(0x4000, 0x10);
+  emulator.memory.write(0xa002, 0x10); // Exposure 0x1000
+  emulator.memory.write(0xa003, 0x00);
+  for cell in 0..16 {
+    for (i, threshold) in [0x40, 0x80, 0xc0].iter().enumerate() {
+      emulator.memory.write(0xa006 + cell * 3 + i as u16, *threshold);
+    }
+  }
+  emulator.memory.write(0xa000, 0x01);
+  assert_eq!(emulator.memory.read(0xa000) & 0x01, 0x01);
+  emulator.memory.cartridge.tick(CPU_CLOCK_HZ);
+  assert_eq!(emulator.memory.read(0xa000) & 0x01, 0x00);
+
+  emulator.memory.write(0x4000, 0x00);
+  assert_eq!(emulator.memory.read(0xa100), 0xff);
+  assert_eq!(emulator.memory.read(0xa101), 0xff);
+  assert_eq!(emulator.memory.read(0xa100 + 8 * 16), 0x00);
+  assert_eq!(emulator.memory.read(0xa101 + 8 * 16), 0x00);
+}
+
+// Clocks `count` bits of `value` into the MBC7 EEPROM, MSB first
+fn eeprom_send(emulator: &mut Emulator, value: u32, count: u32) {
+  for i in (0..count).rev() {
+    let di = ((value >> i) & 1) as u8 * 0b10;
+    emulator.memory.write(0xa080, 0x80 | di);
+    emulator.memory.write(0xa080, 0xc0 | di);
+  }
+}
+
+fn eeprom_receive(emulator: &mut Emulator) -> u16 {
+  (0..16).fold(0, |word, _| {
+    emulator.memory.write(0xa080, 0x80);
+    emulator.memory.write(0xa080, 0xc0);
+    word << 1 | (emulator.memory.read(0xa080) & 0b1) as u16
+  })
+}
+
+#[test]
+fn mbc7_test() {
+  let mut rom = vec![0; 0x200];
+  rom[0x147] = 0x22;
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  assert_eq!(emulator.memory.read(0xa020), 0xff);
+  emulator.memory.write(0x0000, 0x0a);
+  emulator.memory.write(0x4000, 0x40);
+
+  emulator.set_tilt(1.0, -0.5);
+  emulator.memory.write(0xa000, 0x55);
+  assert_eq!(emulator.memory.read(0xa030), 0x80);
+  emulator.memory.write(0xa010, 0xaa);
+  let x = emulator.memory.read(0xa020) as u16 | (emulator.memory.read(0xa030) as u16) << 8;
+  let y = emulator.memory.read(0xa040) as u16 | (emulator.memory.read(0xa050) as u16) << 8;
+  assert_eq!((x, y), (0x81d0 + 0x70, 0x81d0 - 0x38));
+  // No new reading until the next erase
+  emulator.set_tilt(0.0, 0.0);
+  emulator.memory.write(0xa010, 0xaa);
+  assert_eq!(emulator.memory.read(0xa020), 0x40);
+
+  // Writes are ignored until EWEN
+  eeprom_send(&mut emulator, 0x505, 11); // WRITE word 5
+  eeprom_send(&mut emulator, 0x1234, 16);
+  emulator.memory.write(0xa080, 0x00);
+  eeprom_send(&mut emulator, 0x4c0, 11); // EWEN
+  emulator.memory.write(0xa080, 0x00);
+  eeprom_send(&mut emulator, 0x505, 11); // WRITE word 5
+  eeprom_send(&mut emulator, 0xbeef, 16);
+  emulator.memory.write(0xa080, 0x00);
+
+  eeprom_send(&mut emulator, 0x604, 11); // READ from word 4
+  assert_eq!(emulator.memory.read(0xa080) & 0b1, 0);
+  assert_eq!(eeprom_receive(&mut emulator), 0xffff);
+  assert_eq!(eeprom_receive(&mut emulator), 0xbeef);
+  emulator.memory.write(0xa080, 0x00);
+
+  let save = emulator.battery_save().unwrap();
+  assert_eq!(save.len(), 256);
+  assert_eq!(&save[10..12], &[0xef, 0xbe]);
+}
+
+// Runs one TAMA5 command through the register window, returning the read result
+fn tama5_command(emulator: &mut Emulator, command: u8, address: u8, data: u8) -> u8 {
+  let registers = [
+    (0x4, data & 0xf),
+    (0x5, data >> 4),
+    (0x6, command << 1 | address >> 4),
+    (0x7, address & 0xf),
+  ];
+  for (register, value) in registers.iter() {
+    emulator.memory.write(0xa001, *register);
+    emulator.memory.write(0xa000, *value);
+  }
+  emulator.memory.write(0xa001, 0x0c);
+  let low = emulator.memory.read(0xa000) & 0xf;
+  emulator.memory.write(0xa001, 0x0d);
+  let high = emulator.memory.read(0xa000) & 0xf;
+  high << 4 | low
+}
+
+#[test]
+fn tama5_test() {
+  let mut rom = vec![0; 0x40000];
+  for bank in 0..16 {
+    rom[bank * 0x4000 + 0x3000] = bank as u8;
+  }
+  rom[0x147] = 0xfd;
+  rom[0x148] = 0x03;
+  let header = with_header(rom[..0x8000].to_vec());
+  rom[..0x8000].copy_from_slice(&header);
+
+  let mut emulator = Emulator::default();
+  emulator.load_rom(rom.clone()).unwrap();
+  emulator.memory.cartridge.set_clock(Box::new(FixedClock(1_000)));
+  emulator.memory.write(0xa001, 0x0a);
+  assert_eq!(emulator.memory.read(0xa000), 0xf1);
+
+  emulator.memory.write(0xa001, 0x00);
+  emulator.memory.write(0xa000, 0x05);
+  assert_eq!(emulator.memory.read(0x7000), 0x05);
+
+  tama5_command(&mut emulator, 0x0, 0x13, 0xa5);
+  assert_eq!(tama5_command(&mut emulator, 0x1, 0x13, 0x00), 0xa5);
+
+  // 23:59:59, the hours tens digit is register 5
+  for (register, digit) in [(0, 9), (1, 5), (2, 9), (3, 5), (4, 3), (5, 2)].iter() {
+    tama5_command(&mut emulator, 0x2, *register, *digit);
+  }
+  emulator.memory.cartridge.tick(CPU_CLOCK_HZ);
+  let day = |emulator: &mut Emulator| {
+    (0..10)
+      .map(|register| tama5_command(emulator, 0x3, register, 0))
+      .collect::<Vec<u8>>()
+  };
+  assert_eq!(day(&mut emulator), vec![0, 0, 0, 0, 0, 0, 1, 0, 2, 0]);
+
+  let save = emulator.battery_save().unwrap();
+  let mut restored = Emulator::default();
+  restored.load_rom(rom.clone()).unwrap();
+  restored.memory.cartridge.set_clock(Box::new(FixedClock(1_000 + 86_400 + 61)));
+  restored.load_battery_save(&save);
+  assert_eq!(day(&mut restored), vec![1, 0, 1, 0, 0, 0, 2, 0, 3, 0]);
+  assert_eq!(tama5_command(&mut restored, 0x1, 0x13, 0x00), 0xa5);
+
+  // A corrupted clock footer keeps the RAM but not the time
+  let mut corrupted = save;
+  corrupted[0x20 + 8..].iter_mut().for_each(|byte| *byte = 0xff);
+  let mut restored = Emulator::default();
+  restored.load_rom(rom).unwrap();
+  restored.load_battery_save(&corrupted);
+  restored.memory.cartridge.tick(CPU_CLOCK_HZ);
+  assert_eq!(day(&mut restored), vec![1, 0, 0, 0, 0, 0, 0, 0, 1, 0]);
+  assert_eq!(tama5_command(&mut restored, 0x1, 0x13, 0x00), 0xa5);
+
+  // Digits past a field's range are clamped, so snapshots stay loadable
+  tama5_command(&mut restored, 0x2, 1, 9);
+  tama5_command(&mut restored, 0x2, 7, 9);
+  let state = restored.save_state();
+  restored.load_state(&state).unwrap();
+  assert_eq!(day(&mut restored), vec![9, 5, 0, 0, 0, 0, 6, 0, 1, 0]);
+
+  // Bogus timestamps neither overflow nor take ages to catch up
+  let mut save = restored.battery_save().unwrap();
+  save[0x20..0x28].copy_from_slice(&i64::MIN.to_le_bytes());
+  restored.load_battery_save(&save);
+  assert_eq!(day(&mut restored), vec![9, 5, 0, 0, 0, 0, 6, 0, 1, 0]);
+  save[0x20..0x28].copy_from_slice(&(-1_000_000_000_000_000_000i64).to_le_bytes());
+  restored.load_battery_save(&save);
+}
+
+#[test]
+fn rom_header_test() {
+  let mut rom = vec![0; 0x8000];
+  rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+  rom[0x134..0x143].copy_from_slice(b"POKEMON_SLVAAXE");
+  rom[0x143] = 0x80;
+  rom[0x144..0x146].copy_from_slice(b"01");
+  rom[0x146] = 0x03;
+  rom[0x147] = 0x10;
+  rom[0x148] = 0x00;
+  rom[0x149] = 0x03;
+  rom[0x14a] = 0x01;
+  rom[0x14b] = 0x33;
+  rom[0x14c] = 0x02;
+  let mut rom = with_header(rom);
+  let checksum = rom
+    .iter()
+    .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+  rom[0x14e..0x150].copy_from_slice(&checksum.to_be_bytes());
+
+  let header = RomHeader::parse(&rom).unwrap();
+  assert_eq!(header.title, "POKEMON_SLV");
+  assert_eq!(header.manufacturer_code, Some("AAXE".to_string()));
+  assert_eq!(header.new_licensee, Some("01".to_string()));
+  assert_eq!(header.licensee(), "Nintendo R&D1");
+  assert_eq!(header.cgb, CgbSupport::Compatible);
+  assert!(header.sgb);
+  assert_eq!(header.mapper(), "MBC3+TIMER+RAM+BATTERY");
+  assert_eq!(header.rom_size(), Some(0x8000));
+  assert_eq!(header.ram_size(), Some(0x8000));
+  assert_eq!(header.destination, Destination::Overseas);
+  assert_eq!(header.version, 2);
+  assert!(header.header_checksum_valid());
+  assert!(header.global_checksum_valid());
+  assert!(header.logo_valid);
+
+  // DMG games use all 16 bytes for the title and the old licensee code
+  rom[0x104] = 0;
+  rom[0x143] = b'X';
+  rom[0x14b] = 0x01;
+  let header = RomHeader::parse(&rom).unwrap();
+  assert_eq!(header.title, "POKEMON_SLVAAXEX");
+  assert_eq!(header.manufacturer_code, None);
+  assert_eq!(header.licensee(), "Nintendo");
+  assert_eq!(header.cgb, CgbSupport::None);
+  assert!(!header.sgb);
+  assert!(!header.header_checksum_valid());
+  assert!(!header.global_checksum_valid());
+  assert!(!header.logo_valid);
+
+  assert!(matches!(
+    RomHeader::parse(&rom[..0x14f]),
+    Err(LoadError::Truncated { .. })
+  ));
+}
+
+// Pulses P1 like the SGB driver of a game, one packet of 16 bytes at a time
+fn send_sgb_packets(emulator: &mut Emulator, data: &[u8]) {
+  for packet in data.chunks(16) {
+    emulator.memory.write(0xff00, 0x00);
+    emulator.memory.write(0xff00, 0x30);
+    for bit in 0..128 {
+      let byte = packet.get(bit / 8).copied().unwrap_or(0);
+      emulator.memory.write(0xff00, if byte >> (bit % 8) & 1 == 1 { 0x10 } else { 0x20 });
+      emulator.memory.write(0xff00, 0x30);
+    }
+    emulator.memory.write(0xff00, 0x20);
+    emulator.memory.write(0xff00, 0x30);
+  }
+}
+
+fn sgb_pixel(emulator: &Emulator, x: usize, y: usize) -> u32 {
+  let (pixels, width, _) = emulator.screen();
+  pixels[y * width + x]
+}
+
+#[test]
+fn sgb_test() {
+  let mut rom = vec![0; 0x8000];
+  rom[0x146] = 0x03;
+  rom[0x14b] = 0x33;
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  assert!(emulator.memory.sgb.is_some());
+  assert_eq!(emulator.screen().1, 256);
+
+  // White shared color, red for palette 0 and blue for palette 1
+  send_sgb_packets(
+    &mut emulator,
+    &[0x01, 0xff, 0x7f, 0, 0, 0, 0, 0x1f, 0x00, 0, 0, 0, 0, 0x00, 0x7c],
+  );
+  // The left half of the screen uses palette 1
+  send_sgb_packets(&mut emulator, &[0x21, 1, 0b001, 0b01, 0, 0, 9, 17]);
+  let sgb = emulator.memory.sgb.as_ref().unwrap();
+  assert_eq!(sgb.palette(1), [0x7fff, 0, 0, 0x7c00]);
+  assert_eq!(sgb.attribute(9, 17), 1);
+  assert_eq!(sgb.attribute(10, 0), 0);
+
+  emulator.memory.write(0xff47, 0xff);
+  for _ in 0..3 {
+    emulator.run_frame();
+  }
+  assert_eq!(sgb_pixel(&emulator, 48, 40), 0x00_00_ff);
+  assert_eq!(sgb_pixel(&emulator, 48 + 159, 40 + 143), 0xff_00_00);
+  assert_eq!(sgb_pixel(&emulator, 0, 0), 0xff_ff_ff);
+
+  send_sgb_packets(&mut emulator, &[0xb9, 2]);
+  emulator.run_frame();
+  assert_eq!(emulator.memory.sgb.as_ref().unwrap().mask, Mask::Black);
+  assert_eq!(sgb_pixel(&emulator, 48, 40), 0);
+  send_sgb_packets(&mut emulator, &[0xb9, 0]);
+
+  // Transfers read the tiles shown on screen, map them in order to copy VRAM as is
+  for tile in 0..256 {
+    emulator
+      .memory
+      .write_unchecked(0x9800 + (tile / 20) * 32 + tile % 20, tile as u8);
+  }
+  emulator.memory.write_unchecked(0x8000, 0xff);
+  send_sgb_packets(&mut emulator, &[0x99, 0]);
+  // Border palette 4 gets green as color 1, map entries stay on tile 0
+  emulator.memory.write_unchecked(0x8000, 0x00);
+  emulator.memory.write_unchecked(0x8802, 0xe0);
+  emulator.memory.write_unchecked(0x8803, 0x03);
+  send_sgb_packets(&mut emulator, &[0xa1]);
+  emulator.run_frame();
+  assert_eq!(sgb_pixel(&emulator, 0, 0), 0x00_ff_00);
+  assert_eq!(sgb_pixel(&emulator, 0, 1), 0xff_ff_ff);
+
+  let state = emulator.save_state();
+  let frame = emulator.screen().0.to_vec();
+  emulator.load_state(&state).unwrap();
+  assert_eq!(emulator.screen().0, &frame[..]);
+}
+
+// Reads the ID, the directions and the buttons of the current joypad like a game polling P1
+fn poll_sgb_joypad(emulator: &mut Emulator) -> (u8, u8, u8) {
+  emulator.memory.write(0xff00, 0x30);
+  let id = emulator.memory.read(0xff00) & 0x0f;
+  emulator.memory.write(0xff00, 0x20);
+  let directions = emulator.memory.read(0xff00) & 0x0f;
+  emulator.memory.write(0xff00, 0x10);
+  let buttons = emulator.memory.read(0xff00) & 0x0f;
+  (id, directions, buttons)
+}
+
+#[test]
+fn sgb_multiplayer_test() {
+  let mut rom = vec![0; 0x8000];
+  rom[0x146] = 0x03;
+  rom[0x14b] = 0x33;
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  emulator.press_player(0, Button::A);
+  emulator.press_player(1, Button::Right);
+  emulator.press_player(3, Button::Start);
+  emulator.press_player(3, Button::Down);
+
+  // Only the first joypad answers until MLT_REQ
+  assert_eq!(poll_sgb_joypad(&mut emulator), (0xf, 0xf, 0xe));
+  assert_eq!(poll_sgb_joypad(&mut emulator), (0xf, 0xf, 0xe));
+
+  send_sgb_packets(&mut emulator, &[0x89, 0x03]);
+  assert_eq!(emulator.memory.joypad.players(), 4);
+  let polls = (0..5)
+    .map(|_| poll_sgb_joypad(&mut emulator))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    polls,
+    vec![
+      (0xf, 0xf, 0xe),
+      (0xe, 0xe, 0xf),
+      (0xd, 0xf, 0xf),
+      (0xc, 0x7, 0x7),
+      (0xf, 0xf, 0xe),
+    ]
+  );
+
+  emulator.release_player(3, Button::Start);
+  let state = emulator.save_state();
+  emulator.load_state(&state).unwrap();
+  assert_eq!(emulator.memory.joypad.player_buttons(3), 1 << Button::Down as u8);
+
+  // There is no fifth joypad
+  emulator.press_player(4, Button::A);
+  assert_eq!(emulator.memory.joypad.player_buttons(4), 0);
+
+  send_sgb_packets(&mut emulator, &[0x89, 0x01]);
+  assert_eq!(emulator.memory.joypad.players(), 2);
+  let ids = (0..3)
+    .map(|_| poll_sgb_joypad(&mut emulator).0)
+    .collect::<Vec<_>>();
+  assert_eq!(ids, vec![0xf, 0xe, 0xf]);
+}
+
+#[test]
+fn serial_clock_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(vec![0; 0x8000])).unwrap();
+  let transfer_cycles = |emulator: &mut Emulator, sc: u8| {
+    emulator.memory.write(0xff04, 0x00);
+    emulator.step_instruction();
+    emulator.memory.write(0xff02, sc);
+    let start = emulator.cycles;
+    emulator.run_until(|emulator| emulator.memory.read(0xff02) & 0x80 == 0);
+    emulator.cycles - start
+  };
+  // 8 falling edges of divider bit 8, SC bit 1 only selects the fast clock on CGB
+  assert_eq!(transfer_cycles(&mut emulator, 0x81), 4092);
+  assert_eq!(transfer_cycles(&mut emulator, 0x83), 4092);
+  emulator.memory.model = Model::Cgb;
+  assert_eq!(transfer_cycles(&mut emulator, 0x83), 124);
+}
+
+fn serial_exchange(emulator: &mut Emulator, data: u8) -> u8 {
+  emulator.memory.write(0xff01, data);
+  emulator.memory.write(0xff02, 0x81);
+  emulator.run_until(|emulator| emulator.memory.read(0xff02) & 0x80 == 0);
+  emulator.memory.read(0xff01)
+}
+
+// Sends a printer packet and returns the two bytes answered after the checksum
+fn printer_packet(emulator: &mut Emulator, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+  let mut packet = vec![command, compressed as u8];
+  packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+  packet.extend_from_slice(data);
+  let checksum = packet
+    .iter()
+    .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+  let mut bytes = vec![0x88, 0x33];
+  bytes.extend_from_slice(&packet);
+  bytes.extend_from_slice(&checksum.to_le_bytes());
+  for byte in bytes {
+    assert_eq!(serial_exchange(emulator, byte), 0x00);
+  }
+  (serial_exchange(emulator, 0x00), serial_exchange(emulator, 0x00))
+}
+
+fn load_print(path: &std::path::Path) -> (u32, u32, Vec<u8>) {
+  let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+  let (info, mut reader) = decoder.read_info().unwrap();
+  let mut pixels = vec![0; info.buffer_size()];
+  reader.next_frame(&mut pixels).unwrap();
+  (info.width, info.height, pixels)
+}
+
+#[test]
+fn printer_test() {
+  let output_dir = std::env::temp_dir().join("soup_gb_printer_test");
+  let _ = std::fs::remove_dir_all(&output_dir);
+  std::fs::create_dir_all(&output_dir).unwrap();
+  let printer = Printer::new(&output_dir);
+  // Left by an earlier session
+  std::fs::write(printer.print_path(0), b"old").unwrap();
+  let first_print = printer.print_path(1);
+  let second_print = printer.print_path(2);
+
+  // Spins on JR -2 while the transfers run
+  let mut rom = vec![0; 0x8000];
+  rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+  let mut emulator = Emulator::default();
+  emulator.load_rom(with_header(rom)).unwrap();
+  // Nothing answers on the other end of the cable
+  assert_eq!(serial_exchange(&mut emulator, 0x88), 0xff);
+  emulator.connect_serial(Box::new(printer));
+
+  assert_eq!(printer_packet(&mut emulator, 0x01, false, &[]), (0x81, 0x00));
+  // Two tile rows with a black first tile, then two more compressed ones
+  let mut tiles = vec![0; 640];
+  tiles[..16].copy_from_slice(&[0xff; 16]);
+  assert_eq!(printer_packet(&mut emulator, 0x04, false, &tiles), (0x81, 0x08));
+  let compressed = [0x01, 0xaa, 0x55, 0xff, 0, 0xff, 0, 0xff, 0, 0xff, 0, 0xf8, 0];
+  assert_eq!(printer_packet(&mut emulator, 0x04, true, &compressed), (0x81, 0x08));
+  assert_eq!(printer_packet(&mut emulator, 0x04, false, &[]), (0x81, 0x08));
+
+  assert_eq!(printer_packet(&mut emulator, 0x02, false, &[1, 0x13, 0xe4, 0x40]), (0x81, 0x02));
+  let statuses = (0..4)
+    .map(|_| printer_packet(&mut emulator, 0x0f, false, &[]).1)
+    .collect::<Vec<u8>>();
+  assert_eq!(statuses, vec![0x02, 0x02, 0x02, 0x00]);
+
+  let (width, height, pixels) = load_print(&first_print);
+  assert_eq!((width, height), (160, 32));
+  let luminance = |x: usize, y: usize| pixels[(y * 160 + x) * 3];
+  assert_eq!(luminance(0, 0), 0);
+  assert_eq!(luminance(8, 0), 255);
+  assert_eq!(luminance(0, 16), 170);
+  assert_eq!(luminance(1, 16), 85);
+
+  // A corrupted checksum is reported and the packet is dropped
+  for byte in [0x88, 0x33, 0x04, 0x00, 0x01, 0x00, 0x42, 0x00, 0x00].iter() {
+    serial_exchange(&mut emulator, *byte);
+  }
+  assert_eq!(serial_exchange(&mut emulator, 0x00), 0x81);
+  assert_eq!(serial_exchange(&mut emulator, 0x00), 0x01);
+
+  // The lowest exposure lightens the print
+  assert_eq!(printer_packet(&mut emulator, 0x04, true, &compressed), (0x81, 0x08));
+  printer_packet(&mut emulator, 0x02, false, &[1, 0x00, 0xe4, 0x00]);
+  assert_eq!(std::fs::read(output_dir.join("print_000.png")).unwrap(), b"old");
+  let (_, height, pixels) = load_print(&second_print);
+  assert_eq!(height, 16);
+  assert_eq!(pixels[0], 191);
+}
+
+// Stores every read of the direction keys into WRAM, so the final state depends on input timing
+fn joypad_rom() -> Vec<u8> {
+  let mut rom = vec![0; 0x8000];
+  let program = [
+    0x21, 0x00, 0xc0, // LD HL, 0xc000
+    0x3e, 0x20, // LD A, 0x20
+    0xe0, 0x00, // LDH (0x00), A
+    0xf0, 0x00, // LDH A, (0x00)
+    0x22, // LD (HL+), A
+    0xcb, 0x6c, // BIT 5, H - stop at 0xe000
+    0x28, 0xf5, // JR Z, -11
+    0x21, 0x00, 0xc0, // LD HL, 0xc000
+    0x18, 0xf0, // JR -16
+  ];
+  rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+  with_header(rom)
+}
+
+fn record_movie(emulator: &mut Emulator, start: StartState) -> Movie {
+  let mut movie = Movie::record(emulator, start, 1_600_000_000);
+  for frame in 0..30 {
+    match frame {
+      5 => emulator.press(Button::Up),
+      8 => emulator.press_player(1, Button::A),
+      12 => emulator.press(Button::Left),
+      13 => emulator.release(Button::Up),
+      15 => emulator.release_player(1, Button::A),
+      20 => emulator.release(Button::Left),
+      _ => {}
+    }
+    movie.record_input(emulator.frame_count, emulator.memory.joypad.all_buttons());
+    emulator.run_frame();
+  }
+  movie.finish(emulator.frame_count);
+  movie
+}
+
+#[test]
+fn movie_playback_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(joypad_rom()).unwrap();
+  let movie = record_movie(&mut emulator, StartState::PowerOn);
+  let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+  assert_eq!(movie.inputs.len(), 7);
+
+  let mut runner = HeadlessRunner::new(joypad_rom()).unwrap();
+  runner.play(movie).unwrap();
+  // The last input changes at frame 20, playback goes on to where the recording stopped
+  while !runner.movie_finished() {
+    runner.run_frame();
+  }
+  assert_eq!(runner.emulator.frame_count, 30);
+  assert_eq!(runner.emulator.save_state(), emulator.save_state());
+}
+
+#[test]
+fn movie_snapshot_playback_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(joypad_rom()).unwrap();
+  emulator.press(Button::Down);
+  for _ in 0..10 {
+    emulator.run_frame();
+  }
+  let snapshot = emulator.save_state();
+  let movie = record_movie(&mut emulator, StartState::Snapshot(snapshot));
+
+  let mut runner = HeadlessRunner::new(joypad_rom()).unwrap();
+  runner.play(movie.clone()).unwrap();
+  assert_eq!(runner.emulator.frame_count, 10);
+  runner.run(30);
+  assert_eq!(runner.emulator.save_state(), emulator.save_state());
+
+  let mut other_rom = joypad_rom();
+  other_rom[0x150] = 1;
+  let mut runner = HeadlessRunner::new(other_rom).unwrap();
+  assert!(runner.play(movie).is_err());
+}
+
+// Fills the background with a checkerboard of blank tiles and tiles with a diagonal line
+fn tiles_rom() -> Vec<u8> {
+  let mut rom = vec![0; 0x8000];
+  let program = [
+    0xaf, // XOR A
+    0xe0, 0x40, // LDH (0x40), A - LCD off
+    0x21, 0x10, 0x80, // LD HL, 0x8010 - tile 1
+    0x3e, 0x80, // LD A, 0x80
+    0x22, // LD (HL+), A
+    0x22, // LD (HL+), A
+    0x0f, // RRCA
+    0x30, 0xfb, // JR NC, -5
+    0x21, 0x00, 0x98, // LD HL, 0x9800
+    0x7d, // LD A, L
+    0xcb, 0x37, // SWAP A
+    0x0f, // RRCA - A bit 0 = row parity
+    0xad, // XOR L
+    0xe6, 0x01, // AND 0x01
+    0x22, // LD (HL+), A
+    0x7c, // LD A, H
+    0xfe, 0x9c, // CP 0x9c
+    0x20, 0xf3, // JR NZ, -13
+    0x3e, 0x91, // LD A, 0x91
+    0xe0, 0x40, // LDH (0x40), A - LCD on
+    0x18, 0xfe, // JR -2
+  ];
+  rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+  with_header(rom)
+}
+
+#[test]
+fn golden_frame_test() {
+  let mut emulator = Emulator::default();
+  emulator.load_rom(tiles_rom()).unwrap();
+  for _ in 0..3 {
+    emulator.run_frame();
+  }
+  assert_eq!(emulator.frame_hash(), 0x83b2_ee71_e932_7725);
+
+  let path = std::env::temp_dir().join("soup_gb_golden_frame_test.png");
+  emulator.save_screenshot(&path).unwrap();
+  let png = std::fs::read(&path).unwrap();
+  assert_eq!(&png[1..4], b"PNG");
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn load_rom_errors_test() {
+  let mut emulator = Emulator::default();
+  match emulator.load_rom(vec![0; 0x14c]) {
+    Err(LoadError::Truncated { expected, found }) => assert_eq!((expected, found), (0x150, 0x14c)),
+    result => panic!("unexpected {:?}", result),
+  }
+  match emulator.load_rom(vec![0; 0x8000]) {
+    Err(LoadError::HeaderChecksum { expected, found }) => assert_eq!((expected, found), (0xe7, 0)),
+    result => panic!("unexpected {:?}", result),
+  }
+  let mut rom = vec![0; 0x8000];
+  rom[0x148] = 0x01; // 64KB
+  match emulator.load_rom(with_header(rom)) {
+    Err(LoadError::Truncated { expected, found }) => assert_eq!((expected, found), (0x10000, 0x8000)),
+    result => panic!("unexpected {:?}", result),
+  }
+  let mut rom = vec![0; 0x8000];
+  rom[0x147] = 0x1b; // MBC5+RAM+BATTERY
+  match emulator.load_rom(with_header(rom)) {
+    Err(LoadError::UnsupportedMapper(0x1b)) => {}
+    result => panic!("unexpected {:?}", result),
+  }
+  let mut rom = vec![0; 0x8000];
+  rom[0x147] = 0x03;
+  rom[0x149] = 0x04; // 128KB RAM, MBC1 only addresses 32KB
+  match emulator.load_rom(with_header(rom)) {
+    Err(LoadError::InvalidRamSize(0x04)) => {}
+    result => panic!("unexpected {:?}", result),
+  }
+
+  let mut rom = vec![0; 0x8000];
+  rom[0x134..0x138].copy_from_slice(b"SOUP");
+  rom[0x147] = 0x13;
+  rom[0x149] = 0x03;
+  let info = emulator.load_rom(with_header(rom)).unwrap();
+  assert_eq!(info.title, "SOUP");
+  assert_eq!(info.mapper(), "MBC3+RAM+BATTERY");
+  assert_eq!(info.ram_size, 0x8000);
 }